@@ -0,0 +1,182 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::utility::{Error, Extension, TargetPlatform};
+use crate::vsix::verify_vsix;
+
+// Every download is kept under <cache>/<publisher>/<name>/<version>/<platform>.vsix, so the same
+// artifact is only ever fetched once whatever it is saved as
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("get-vsix"))
+}
+
+pub fn entry_path(extension: &Extension, index: usize) -> Option<PathBuf> {
+    let version = &extension.versions[index];
+
+    cache_dir().map(|dir| {
+        dir.join(extension.publisher.publisherName.to_lowercase())
+            .join(extension.extensionName.to_lowercase())
+            .join(&version.version)
+            .join(format!(
+                "{}.vsix",
+                version.targetPlatform.unwrap_or(TargetPlatform::Universal)
+            ))
+    })
+}
+
+// Copies the cached vsix to path and gives back its sha256, None when it isn't cached. An entry that
+// isn't a whole vsix anymore is removed so the download replaces it
+pub fn fetch(extension: &Extension, index: usize, path: &str) -> Result<Option<String>, Error> {
+    let entry = match entry_path(extension, index) {
+        Some(entry) if entry.is_file() => entry,
+        _ => return Ok(None),
+    };
+
+    if verify_vsix(&entry.to_string_lossy()).is_err() {
+        fs::remove_file(&entry).map_err(Error::FileDelete)?;
+        return Ok(None);
+    }
+
+    fs::copy(&entry, path).map_err(Error::FileWrite)?;
+
+    let mut hasher = Sha256::new();
+    let mut file = File::open(path).map_err(Error::FileRead)?;
+    io::copy(&mut file, &mut hasher).map_err(Error::FileRead)?;
+
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+// The copy is renamed into place so another run never picks up half of it
+pub fn store(extension: &Extension, index: usize, path: &str) -> Result<(), Error> {
+    let entry = match entry_path(extension, index) {
+        Some(entry) => entry,
+        None => return Ok(()),
+    };
+
+    if let Some(dir) = entry.parent() {
+        fs::create_dir_all(dir).map_err(Error::FileWrite)?;
+    }
+
+    let partial = entry.with_extension(format!("vsix.{}.part", std::process::id()));
+    fs::copy(path, &partial).map_err(Error::FileWrite)?;
+    fs::rename(&partial, &entry).map_err(Error::FileWrite)
+}
+
+#[derive(Serialize, Debug)]
+pub struct CacheEntry {
+    pub id: String,
+    pub version: String,
+    pub platform: String,
+    pub size: u64,
+    #[serde(skip)]
+    pub path: PathBuf,
+    #[serde(skip)]
+    pub modified: SystemTime,
+}
+
+impl CacheEntry {
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.modified)
+            .unwrap_or_default()
+    }
+}
+
+// Sorted by id then version, a missing cache is an empty one
+pub fn list() -> Result<Vec<CacheEntry>, Error> {
+    let dir = match cache_dir() {
+        Some(dir) if dir.is_dir() => dir,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut entries = Vec::new();
+
+    for publisher in read_dirs(&dir)? {
+        for name in read_dirs(&publisher)? {
+            for version in read_dirs(&name)? {
+                for file in fs::read_dir(&version).map_err(Error::FileRead)? {
+                    let path = file.map_err(Error::FileRead)?.path();
+                    if path.extension().is_none_or(|extension| extension != "vsix") {
+                        continue;
+                    }
+
+                    let metadata = fs::metadata(&path).map_err(Error::FileRead)?;
+                    let file_name = |path: &Path| {
+                        path.file_name()
+                            .map(|name| name.to_string_lossy().to_string())
+                            .unwrap_or_default()
+                    };
+
+                    entries.push(CacheEntry {
+                        id: format!("{}.{}", file_name(&publisher), file_name(&name)),
+                        version: file_name(&version),
+                        platform: path
+                            .file_stem()
+                            .map(|stem| stem.to_string_lossy().to_string())
+                            .unwrap_or_default(),
+                        size: metadata.len(),
+                        modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                        path,
+                    });
+                }
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| (&a.id, &a.version).cmp(&(&b.id, &b.version)));
+    Ok(entries)
+}
+
+fn read_dirs(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    Ok(fs::read_dir(dir)
+        .map_err(Error::FileRead)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect())
+}
+
+// Removes the entries older than max_age, then the oldest ones until the cache fits in max_size.
+// Without either limit the whole cache goes. Gives back what was removed
+pub fn clean(max_age: Option<Duration>, max_size: Option<u64>) -> Result<Vec<CacheEntry>, Error> {
+    let mut entries = list()?;
+    entries.sort_by_key(|entry| entry.modified);
+
+    let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+    let mut removed = Vec::new();
+
+    for entry in entries {
+        let expired = match (max_age, max_size) {
+            (None, None) => true,
+            _ => {
+                max_age.is_some_and(|max_age| entry.age() > max_age)
+                    || max_size.is_some_and(|max_size| size > max_size)
+            }
+        };
+
+        if !expired {
+            continue;
+        }
+
+        fs::remove_file(&entry.path).map_err(Error::FileDelete)?;
+        size -= entry.size;
+
+        // The folders of the versions and extensions that are left empty go too
+        let mut dir = entry.path.parent();
+        while let Some(parent) = dir.filter(|parent| Some(*parent) != cache_dir().as_deref()) {
+            if fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+
+        removed.push(entry);
+    }
+
+    Ok(removed)
+}
@@ -0,0 +1,706 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use futures::StreamExt;
+use reqwest::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, RANGE};
+use reqwest::{Method, Url};
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+
+use crate::openvsx;
+use crate::telemetry::record_request;
+use crate::utility::{
+    is_api_version_error, query_flags, with_deadline, Error, ExpectedAnswer, Extension, FilterType,
+    RequestCriteria, RequestFilters, RequestFlags, RequestOptions, Results, TargetPlatform,
+};
+use crate::vsix::verify_vsix;
+
+pub const DEFAULT_API_URL: &str =
+    "https://marketplace.visualstudio.com/_apis/public/gallery/extensionquery";
+pub const DEFAULT_API_VERSION: &str = "7.2-preview.1";
+pub const DEFAULT_TARGET: &str = "Microsoft.VisualStudio.Code";
+
+// The protocol the api url speaks
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Registry {
+    // The extensionquery api of the Visual Studio marketplace, which Open VSX also serves a copy of
+    #[default]
+    Marketplace,
+    // The REST api of Open VSX
+    Openvsx,
+}
+
+impl Registry {
+    // Only the REST api of Open VSX lives under a path ending with /api
+    pub fn detect(api_url: &str) -> Registry {
+        if api_url.trim_end_matches('/').ends_with("/api") {
+            Registry::Openvsx
+        } else {
+            Registry::Marketplace
+        }
+    }
+}
+
+// Queries an extension gallery and downloads from it, configured with GalleryClient::builder()
+#[derive(Clone, Debug)]
+pub struct GalleryClient {
+    client: reqwest::Client,
+    api_url: String,
+    api_version: String,
+    target: String,
+    strict: bool,
+    registry: Registry,
+    attempt: u32,
+    token: Option<HeaderValue>,
+}
+
+#[derive(Default, Debug)]
+pub struct GalleryClientBuilder {
+    client: Option<reqwest::Client>,
+    api_url: Option<String>,
+    api_version: Option<String>,
+    target: Option<String>,
+    strict: bool,
+    registry: Option<Registry>,
+    attempt: Option<u32>,
+    token: Option<String>,
+}
+
+impl GalleryClientBuilder {
+    // A file:// url replays a response saved earlier instead of sending the query
+    pub fn api_url(mut self, api_url: impl Into<String>) -> Self {
+        self.api_url = Some(api_url.into());
+        self
+    }
+
+    pub fn api_version(mut self, api_version: impl Into<String>) -> Self {
+        self.api_version = Some(api_version.into());
+        self
+    }
+
+    // The product the searches are made for, Microsoft.VisualStudio.Code by default
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    // Lets the caller set up proxies, headers and timeouts on the client
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    // Fail on response fields this version doesn't know about instead of ignoring them
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    // Detected from the api url when it isn't set
+    pub fn registry(mut self, registry: Registry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    // Sent as a bearer authorization with the queries, never with the downloads
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    // Which try at the same query this client sends, only used to count the retries with --verbose
+    pub fn attempt(mut self, attempt: u32) -> Self {
+        self.attempt = Some(attempt);
+        self
+    }
+
+    pub fn build(self) -> Result<GalleryClient, Error> {
+        let client = match self.client {
+            Some(client) => client,
+            None => reqwest::Client::builder()
+                .build()
+                .map_err(Error::ClientBuild)?,
+        };
+
+        let api_url = self.api_url.unwrap_or_else(|| DEFAULT_API_URL.to_string());
+
+        Ok(GalleryClient {
+            client,
+            registry: self.registry.unwrap_or_else(|| Registry::detect(&api_url)),
+            api_url,
+            api_version: self
+                .api_version
+                .unwrap_or_else(|| DEFAULT_API_VERSION.to_string()),
+            target: self.target.unwrap_or_else(|| DEFAULT_TARGET.to_string()),
+            strict: self.strict,
+            attempt: self.attempt.unwrap_or(1),
+            token: self.token.as_deref().map(bearer).transpose()?,
+        })
+    }
+}
+
+impl GalleryClient {
+    pub fn builder() -> GalleryClientBuilder {
+        GalleryClientBuilder::default()
+    }
+
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    // Searches the text in the names and descriptions, like the search box of the editor
+    pub async fn search(&self, text: &str, page_size: i16) -> Result<Vec<Extension>, Error> {
+        let options = RequestOptions {
+            filters: vec![RequestFilters {
+                pageNumber: 1,
+                pageSize: page_size,
+                criteria: vec![
+                    RequestCriteria {
+                        filterType: FilterType::SearchText as i8,
+                        value: text.to_string(),
+                    },
+                    RequestCriteria {
+                        filterType: FilterType::Target as i8,
+                        value: self.target.clone(),
+                    },
+                    RequestCriteria {
+                        filterType: FilterType::ExcludeWithFlags as i8,
+                        value: (RequestFlags::Unpublished as i32).to_string(),
+                    },
+                ],
+            }],
+            flags: query_flags(),
+        };
+
+        self.query(&options).await
+    }
+
+    pub async fn query(&self, options: &RequestOptions) -> Result<Vec<Extension>, Error> {
+        let body = self.query_raw(options).await?;
+        parse_response(&body, self.strict)
+    }
+
+    // The body of the response as the gallery sent it
+    pub async fn query_raw(&self, options: &RequestOptions) -> Result<String, Error> {
+        if let Some(path) = self.api_url.strip_prefix("file://") {
+            return fs::read_to_string(path).map_err(Error::FileRead);
+        }
+
+        // Answered in the shape of the marketplace so saving and replaying the response works the same
+        if self.registry == Registry::Openvsx {
+            let extensions = openvsx::query(
+                &self.client,
+                &self.api_url,
+                options,
+                self.attempt,
+                self.token.as_ref(),
+            )
+            .await?;
+            let answer = ExpectedAnswer {
+                results: vec![Results { extensions }],
+            };
+
+            return serde_json::to_string(&answer)
+                .map_err(|error| Error::InvalidJson("the Open VSX response".to_string(), error));
+        }
+
+        let url = format!("{}?api-version={}", self.api_url, self.api_version);
+
+        let start = Instant::now();
+        let request = self
+            .client
+            .post(&url)
+            .header(CONTENT_TYPE, "application/json")
+            .json(options);
+        let resp = authorize(request, &url, &self.api_url, self.token.as_ref())
+            .send()
+            .await;
+        record_request(
+            "query",
+            &url,
+            self.attempt,
+            request_outcome(&resp),
+            start.elapsed(),
+        );
+        let resp = resp.map_err(Error::ReqwestDns)?;
+
+        if resp.status().is_client_error() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+
+            if is_api_version_error(status, &body) {
+                return Err(Error::ApiVersion(self.api_version.clone(), body));
+            }
+
+            return Err(Error::Query(status.to_string(), body));
+        }
+
+        resp.text().await.map_err(Error::JsonParse)
+    }
+
+    // Saves the vsix of the version at path and gives back its sha256. A download that is cut short
+    // or isn't a whole vsix is removed instead of being left at path
+    pub async fn download(
+        &self,
+        extension: &Extension,
+        index: usize,
+        path: &Path,
+    ) -> Result<String, Error> {
+        let urls = extension
+            .versions
+            .get(index)
+            .map(|version| version.vsix_urls())
+            .unwrap_or_default();
+        let resp = send_asset(&self.client, Method::GET, &urls, 0, None, None, 1).await?;
+        let total = resp.content_length().ok_or(Error::ReqwestLength())?;
+
+        let result = save_body(resp, path, total).await.and_then(|sha256| {
+            verify_vsix(&path.to_string_lossy())?;
+            Ok(sha256)
+        });
+
+        if result.is_err() && path.exists() {
+            fs::remove_file(path).map_err(Error::FileDelete)?;
+        }
+
+        result
+    }
+}
+
+pub fn parse_response(body: &str, strict: bool) -> Result<Vec<Extension>, Error> {
+    let mut deserializer = serde_json::Deserializer::from_str(body);
+    let mut unknown = Vec::new();
+
+    // serde_ignored sees every field serde skips, which is how strict mode catches schema changes
+    let mut answer: ExpectedAnswer =
+        serde_ignored::deserialize(&mut deserializer, |path| unknown.push(path.to_string()))
+            .map_err(|error| Error::InvalidJson("the query response".to_string(), error))?;
+
+    if strict && !unknown.is_empty() {
+        return Err(Error::UnknownFields(unknown.join(", ")));
+    }
+
+    if answer.results.is_empty() {
+        return Err(Error::EmptyResponse());
+    }
+
+    Ok(dedupe_extensions(answer.results.swap_remove(0).extensions))
+}
+
+// The gallery can list the same extension more than once, only the entry with the most
+// versions and files is kept, at the place the extension first appeared. The ids are guids
+// whose case can differ between entries
+fn dedupe_extensions(extensions: Vec<Extension>) -> Vec<Extension> {
+    let richness = |extension: &Extension| {
+        extension.versions.len()
+            + extension
+                .versions
+                .iter()
+                .map(|version| version.files.len())
+                .sum::<usize>()
+    };
+
+    let mut deduped: Vec<Extension> = Vec::with_capacity(extensions.len());
+
+    for extension in extensions {
+        match deduped.iter_mut().find(|kept| {
+            kept.extensionId
+                .eq_ignore_ascii_case(&extension.extensionId)
+        }) {
+            Some(kept) if richness(&extension) > richness(kept) => *kept = extension,
+            Some(_) => {}
+            None => deduped.push(extension),
+        }
+    }
+
+    deduped
+}
+
+// The newest version built for the platform, else the newest universal one, else the newest at all
+pub fn select_version(
+    extension: &Extension,
+    pre_release: bool,
+    target_platform: TargetPlatform,
+) -> usize {
+    let candidates: Vec<usize> = extension
+        .versions
+        .iter()
+        .enumerate()
+        .filter(|(_, version)| pre_release || !version.is_pre_release())
+        .map(|(i, _)| i)
+        .collect();
+
+    prefer_platform(extension, &candidates, target_platform).unwrap_or(0)
+}
+
+// The build of exactly this version for the platform, pre-release or not since it was asked for by name
+pub fn find_version(
+    extension: &Extension,
+    version: &str,
+    target_platform: TargetPlatform,
+) -> Option<usize> {
+    let candidates: Vec<usize> = extension
+        .versions
+        .iter()
+        .enumerate()
+        .filter(|(_, candidate)| candidate.version == version)
+        .map(|(i, _)| i)
+        .collect();
+
+    prefer_platform(extension, &candidates, target_platform)
+}
+
+// A universal build runs anywhere, so it beats a build for another platform
+fn prefer_platform(
+    extension: &Extension,
+    candidates: &[usize],
+    target_platform: TargetPlatform,
+) -> Option<usize> {
+    candidates
+        .iter()
+        .copied()
+        .find(|i| extension.versions[*i].targetPlatform == Some(target_platform))
+        .or_else(|| {
+            candidates.iter().copied().find(|i| {
+                matches!(
+                    extension.versions[*i].targetPlatform,
+                    None | Some(TargetPlatform::Universal)
+                )
+            })
+        })
+        .or(candidates.first().copied())
+}
+
+async fn save_body(resp: reqwest::Response, path: &Path, total: u64) -> Result<String, Error> {
+    let mut file = BufWriter::new(File::create(path).map_err(Error::FileWrite)?);
+    let mut hasher = Sha256::new();
+    let mut stream = resp.bytes_stream();
+    let mut received = 0;
+
+    while let Some(chunk) = stream.next().await {
+        let Some(chunk) = body_chunk(chunk, received, total)? else {
+            break;
+        };
+        received += chunk.len() as u64;
+        hasher.update(&chunk);
+        file.write_all(&chunk).map_err(Error::FileWrite)?;
+    }
+
+    file.flush().map_err(Error::FileWrite)?;
+    check_length(total, received)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// A connection closed before the announced length is a truncated download, not a network error.
+// None ends the body, check_length then reports how much of it is missing
+pub fn body_chunk<T>(
+    chunk: Result<T, reqwest::Error>,
+    received: u64,
+    total: u64,
+) -> Result<Option<T>, Error> {
+    match chunk {
+        Ok(chunk) => Ok(Some(chunk)),
+        Err(_) if received < total => Ok(None),
+        Err(error) => Err(Error::ReqwestDns(error)),
+    }
+}
+
+// The stream can end early without any error, which would otherwise save a truncated file
+pub fn check_length(expected: u64, got: u64) -> Result<(), Error> {
+    if got == expected {
+        Ok(())
+    } else {
+        Err(Error::IncompleteDownload { expected, got })
+    }
+}
+
+// Tries each url in turn, a range is only asked for when the offset or the end is set. Every url
+// after the first counts as another attempt, the response carries the RequestId it was recorded as
+pub async fn send_asset(
+    client: &reqwest::Client,
+    method: Method,
+    urls: &[String],
+    offset: u64,
+    end: Option<u64>,
+    deadline: Option<Instant>,
+    attempt: u32,
+) -> Result<reqwest::Response, Error> {
+    let mut last_error = Error::NoAssets();
+
+    for (index, url) in urls.iter().enumerate() {
+        let download_url = match Url::parse(url) {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                last_error = Error::UrlParse();
+                continue;
+            }
+        };
+
+        let mut request = client.request(method.clone(), download_url);
+        match end {
+            Some(end) => request = request.header(RANGE, format!("bytes={}-{}", offset, end)),
+            None if offset > 0 => request = request.header(RANGE, format!("bytes={}-", offset)),
+            None => {}
+        }
+
+        let start = Instant::now();
+        let resp = with_deadline(deadline, async {
+            let resp = request.send().await;
+            let id = record_request(
+                "download",
+                url,
+                attempt + index as u32,
+                request_outcome(&resp),
+                start.elapsed(),
+            );
+
+            resp.and_then(|mut resp| {
+                resp.extensions_mut().insert(id);
+                resp.error_for_status()
+            })
+            .map_err(Error::ReqwestDns)
+        })
+        .await;
+
+        match resp {
+            Ok(resp) => return Ok(resp),
+            Err(Error::Deadline()) => return Err(Error::Deadline()),
+            Err(error) => last_error = error,
+        }
+    }
+
+    Err(last_error)
+}
+
+pub fn bearer(token: &str) -> Result<HeaderValue, Error> {
+    let mut authorization =
+        HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|_| Error::InvalidToken())?;
+    authorization.set_sensitive(true);
+    Ok(authorization)
+}
+
+// The token only goes to the host of the api url, never to the hosts an answer points the
+// downloads, icons and checksums at
+pub fn authorize(
+    request: reqwest::RequestBuilder,
+    url: &str,
+    api_url: &str,
+    token: Option<&HeaderValue>,
+) -> reqwest::RequestBuilder {
+    let host = |url: &str| {
+        Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+    };
+
+    match token {
+        Some(token) if host(url).is_some() && host(url) == host(api_url) => {
+            request.header(AUTHORIZATION, token.clone())
+        }
+        _ => request,
+    }
+}
+
+pub(crate) fn request_outcome(resp: &Result<reqwest::Response, reqwest::Error>) -> String {
+    match resp {
+        Ok(resp) => resp.status().to_string(),
+        Err(error) => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::{
+        Files, Properties, Publisher, Versions, PRE_RELEASE_PROPERTY, VSIX_ASSET_TYPE,
+    };
+
+    fn version(version: &str, pre_release: bool) -> Versions {
+        Versions {
+            version: version.to_string(),
+            targetPlatform: None,
+            flags: String::new(),
+            lastUpdated: String::new(),
+            files: vec![Files {
+                assetType: VSIX_ASSET_TYPE.to_string(),
+                source: format!("https://example.com/{}.vsix", version),
+            }],
+            properties: if pre_release {
+                vec![Properties {
+                    key: PRE_RELEASE_PROPERTY.to_string(),
+                    value: "true".to_string(),
+                }]
+            } else {
+                Vec::new()
+            },
+            assetUri: String::new(),
+            fallbackAssetUri: String::new(),
+        }
+    }
+
+    fn extension(id: &str, name: &str, versions: Vec<Versions>) -> Extension {
+        Extension {
+            publisher: Publisher {
+                publisherId: "publisher".to_string(),
+                publisherName: "publisher".to_string(),
+                displayName: "Publisher".to_string(),
+                flags: String::new(),
+                domain: None,
+                isDomainVerified: false,
+            },
+            extensionId: id.to_string(),
+            extensionName: name.to_string(),
+            displayName: name.to_string(),
+            flags: String::new(),
+            lastUpdated: String::new(),
+            publishedDate: String::new(),
+            releaseDate: String::new(),
+            shortDescription: None,
+            versions,
+            statistics: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn pre_release_at_index_0_is_skipped() {
+        let extension = extension(
+            "id",
+            "name",
+            vec![version("1.1.0", true), version("1.0.0", false)],
+        );
+
+        assert_eq!(
+            select_version(&extension, false, TargetPlatform::LinuxX64),
+            1
+        );
+        assert_eq!(
+            select_version(&extension, true, TargetPlatform::LinuxX64),
+            0
+        );
+    }
+
+    #[test]
+    fn only_pre_releases_picks_the_newest() {
+        let extension = extension(
+            "id",
+            "name",
+            vec![version("1.1.0", true), version("1.0.0", true)],
+        );
+
+        assert_eq!(
+            select_version(&extension, false, TargetPlatform::LinuxX64),
+            0
+        );
+    }
+
+    #[test]
+    fn duplicate_ids_keep_the_richest_entry_in_place() {
+        let extensions = vec![
+            extension("A1B2", "first", vec![version("1.0.0", false)]),
+            extension("other", "second", vec![version("1.0.0", false)]),
+            extension(
+                "a1b2",
+                "richer",
+                vec![version("1.1.0", false), version("1.0.0", false)],
+            ),
+        ];
+
+        let deduped = dedupe_extensions(extensions);
+
+        let names: Vec<&str> = deduped
+            .iter()
+            .map(|extension| extension.extensionName.as_str())
+            .collect();
+        assert_eq!(names, ["richer", "second"]);
+    }
+
+    #[test]
+    fn empty_results_is_an_error() {
+        let result = parse_response(r#"{"results":[]}"#, false);
+        assert!(matches!(result, Err(Error::EmptyResponse())));
+    }
+
+    #[test]
+    fn token_only_goes_to_the_api_host() {
+        let client = reqwest::Client::new();
+        let api_url = "https://marketplace.example.com/_apis/public/gallery/extensionquery";
+        let token = bearer("secret").unwrap();
+
+        let authorized = |url: &str| {
+            authorize(client.get(url), url, api_url, Some(&token))
+                .build()
+                .unwrap()
+                .headers()
+                .contains_key(AUTHORIZATION)
+        };
+
+        assert!(authorized(api_url));
+        assert!(!authorized("https://cdn.example.com/extension.vsix"));
+        assert!(!authorized(
+            "https://marketplace.example.com.evil.com/extension.vsix"
+        ));
+    }
+
+    // Announces more bytes than it sends, then closes the connection
+    async fn short_body_server() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            let _ = stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nshort",
+                )
+                .await;
+            let _ = stream.shutdown().await;
+        });
+
+        format!("http://127.0.0.1:{}/ext.vsix", port)
+    }
+
+    #[tokio::test]
+    async fn short_body_is_an_incomplete_download() {
+        let mut version = version("1.0.0", false);
+        version.files[0].source = short_body_server().await;
+        let extension = extension("id", "name", vec![version]);
+        let path = std::env::temp_dir().join(format!("gallery-short-{}.vsix", std::process::id()));
+
+        let client = GalleryClient::builder().build().unwrap();
+        let result = client.download(&extension, 0, &path).await;
+
+        assert!(
+            matches!(
+                result,
+                Err(Error::IncompleteDownload {
+                    expected: 100,
+                    got: 5
+                })
+            ),
+            "{:?}",
+            result
+        );
+        assert!(!path.exists(), "the truncated file wasn't removed");
+    }
+
+    #[tokio::test]
+    async fn download_of_a_missing_version_is_an_error() {
+        let extension = extension("id", "name", vec![version("1.0.0", false)]);
+        let path =
+            std::env::temp_dir().join(format!("gallery-missing-{}.vsix", std::process::id()));
+
+        let client = GalleryClient::builder().build().unwrap();
+        let result = client.download(&extension, 1, &path).await;
+
+        assert!(matches!(result, Err(Error::NoAssets())), "{:?}", result);
+    }
+}
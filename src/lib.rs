@@ -0,0 +1,9 @@
+//! Queries the Visual Studio Code marketplace and downloads extensions from it. The get-vsix
+//! binary is built on top of this, `gallery::GalleryClient` is the place to start.
+
+pub mod cache;
+pub mod gallery;
+pub mod openvsx;
+pub mod telemetry;
+pub mod utility;
+pub mod vsix;
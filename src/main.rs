@@ -1,268 +1,3616 @@
+use std::collections::{BTreeMap, HashSet};
 use std::env;
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use std::time::Instant;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use futures::StreamExt;
-use reqwest::Url;
-use reqwest::{self, header::CONTENT_TYPE};
+use reqwest::header::{ACCEPT_RANGES, CONTENT_TYPE};
+use reqwest::{self, Method, StatusCode};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
 
-mod utility;
-use utility::RequestOptions;
+mod select;
+mod serve;
 
-use crate::utility::{
-    format_size, get_target_platform, input, install_extension, move_to, Ansi, Error,
-    ExpectedAnswer, FilterType, RequestCriteria, RequestFilters, RequestFlags,
+use crate::select::select;
+use crate::serve::serve;
+use get_vsix::cache;
+use get_vsix::gallery::{
+    self, authorize, bearer, body_chunk, check_length, find_version, parse_response,
+    select_version, send_asset, GalleryClient, Registry,
+};
+use get_vsix::openvsx;
+use get_vsix::telemetry::{print_telemetry, record_transfer, request_id, TimedResolver};
+use get_vsix::utility::{
+    check_checksum_file, check_disk_space, check_lockfile, confirm, copy_to_clipboard,
+    engine_compatible, format_size, get_target_platform, history_key, input, install_extension,
+    install_extension_remote, installed_version, is_extension_id, is_newer_version,
+    list_installed_extensions, looks_like_index, move_to, normalize_version, parse_index,
+    print_error, print_output, print_saved_path, program_arch, program_version, query_flags,
+    read_config, read_history, redirect_stdout, save_history, set_emoji, set_error_color,
+    set_program_arch, set_target_platform, strip_jsonc, unicode_supported, unique_tmp_path,
+    with_deadline, Backoff, BarStyle, BatchResult, BatchStatus, ColorChoice, DownloadOutput, Error,
+    Extension, FilterType, Glyph, Jitter, Metadata, PartialMeta, PartialSidecar, ProgressBar,
+    ProgressEvent, RequestCriteria, RequestFilters, RequestFlags, RequestOptions, Resolution,
+    TargetPlatform, Versions, ICON_ASSET_TYPE,
+};
+use get_vsix::vsix::{
+    manifest_dependencies, manifest_id, missing_contributions, preview_themes, print_manifest,
+    repackage_tar_gz, verify_archive, verify_vsix,
 };
 
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(version, about, subcommand_negates_reqs = true)]
 struct Args {
     /// The name of the extension you are looking for
-    #[arg(required = true)]
-    search: String,
-    /// URL for the Visual Studio Code marketplace
-    #[arg(
-        short,
-        long,
-        default_value = "https://marketplace.visualstudio.com/_apis/public/gallery/extensionquery"
-    )]
-    api: String,
+    #[arg(required_unless_present_any = ["from_file", "probe", "auto_update", "serve", "resume_all", "verify_only", "refresh_metadata"])]
+    search: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// URL for the Visual Studio Code marketplace, overrides the preset
+    #[arg(global = true, short, long)]
+    api: Option<String>,
+    /// A known marketplace to use instead of typing its URL
+    #[arg(global = true, long, value_enum, default_value_t = ApiPreset::Vscode)]
+    api_preset: ApiPreset,
+    /// Which api the URL speaks, a URL ending with /api is taken for the Open VSX REST api
+    #[arg(global = true, long, value_enum)]
+    registry: Option<Registry>,
     /// How many extensions to show
-    #[arg(short, long, default_value_t = 5)]
+    #[arg(global = true, short, long, default_value_t = 5)]
     limit: i16,
     /// The version of the api
-    #[arg(short = 'v', long, default_value = "7.2-preview.1")]
+    #[arg(global = true, short = 'v', long, default_value = gallery::DEFAULT_API_VERSION)]
     api_version: String,
+    /// Retry with the fallback api versions when the marketplace rejects the api version
+    #[arg(global = true, long)]
+    prefer_stable_api: bool,
+    /// The api versions tried in order by --prefer-stable-api
+    #[arg(
+        global = true,
+        long,
+        value_delimiter = ',',
+        default_value = "7.1-preview.1,3.0-preview.1"
+    )]
+    api_fallback: Vec<String>,
     /// The program to use to install the extension
-    #[arg(short, long, default_value = "codium")]
+    #[arg(global = true, short, long, default_value = "codium")]
     program: String,
+    /// Pick the platform from the architecture the program reports instead of the one of this machine
+    #[arg(global = true, long)]
+    platform_from_program: bool,
+    /// Download the builds for this platform instead of the one of this machine, to install them on another
+    #[arg(
+        global = true,
+        long,
+        value_enum,
+        value_name = "PLATFORM",
+        conflicts_with = "platform_from_program"
+    )]
+    target_platform: Option<TargetPlatform>,
+    /// Download the build of every platform the version has, with the platform in the file names
+    #[arg(global = true, long, conflicts_with_all = ["target_platform", "platform_from_program", "from_file", "auto_update", "since_version", "resolve_only", "with_dependencies", "expand_packs_recursively"])]
+    all_platforms: bool,
+    /// Only pick versions that work with this editor version, auto asks the program for its version
+    #[arg(global = true, long, value_name = "VERSION")]
+    engine: Option<String>,
     /// Where the file is saved
-    #[arg(short, long, default_value = "./")]
+    #[arg(global = true, short, long, default_value = "./")]
     output: String,
+    /// What pressing Enter without typing anything answers to the yes or no questions
+    #[arg(global = true, long, value_enum, default_value_t = DefaultAnswer::Yes)]
+    default_answer: DefaultAnswer,
+    /// Don't ask anything: pick the exact id or else the first result, download and install it
+    #[arg(global = true, short, long)]
+    yes: bool,
+    /// Save the download to the output folder instead of asking to install it
+    #[arg(global = true, long)]
+    no_install: bool,
+    /// Type the index of the extension instead of picking it with the arrow keys
+    #[arg(global = true, long)]
+    type_index: bool,
+    /// Refine the search from the results list before picking an extension
+    #[arg(global = true, short, long)]
+    interactive: bool,
+    /// Don't remember or suggest the extension picked for a search
+    #[arg(global = true, long)]
+    no_history: bool,
+    /// Maximum number of seconds the query and download may take in total
+    #[arg(global = true, long)]
+    deadline: Option<u64>,
+    /// Report the download progress as newline delimited json on stderr
+    #[arg(global = true, long)]
+    progress_json: bool,
+    /// Serve the vsix files of this folder over http so other machines can install from it
+    #[arg(global = true, long, value_name = "DIR")]
+    serve: Option<String>,
+    /// Check vsix files or folders of them without downloading anything, the search can be the
+    /// expected publisher.name@version
+    #[arg(global = true, long, value_name = "PATH", num_args = 1..)]
+    verify_only: Vec<String>,
+    /// The port used by --serve
+    #[arg(global = true, long, default_value_t = 8080, requires = "serve")]
+    port: u16,
+    /// The address --serve listens on, 0.0.0.0 makes the folder reachable from the whole network
+    #[arg(
+        global = true,
+        long,
+        value_name = "HOST",
+        default_value = "127.0.0.1",
+        requires = "serve"
+    )]
+    serve_host: String,
+    /// Check the connection to the marketplace without searching anything
+    #[arg(global = true, long)]
+    probe: bool,
+    /// Send requests through this proxy
+    #[arg(global = true, long, value_name = "URL")]
+    proxy: Option<String>,
+    /// Read the defaults from this file instead of ~/.config/get-vsix/config.toml
+    #[arg(global = true, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Ignore the config file
+    #[arg(global = true, long, conflicts_with = "config")]
+    no_config: bool,
+    /// Always download, without using or filling the cache
+    #[arg(global = true, long)]
+    no_cache: bool,
+    /// Token sent as a bearer authorization to the marketplace
+    #[arg(global = true, long)]
+    token: Option<String>,
+    /// Print the timings of every request once the run is over
+    #[arg(global = true, long)]
+    verbose: bool,
+    /// Don't print the informational banners like "Found 5 extensions"
+    #[arg(global = true, short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Only print the paths of the saved files on stdout, everything else goes to stderr
+    #[arg(global = true, long, alias = "path-only")]
+    suppress_success_output: bool,
+    /// Only connect to the marketplace over IPv4
+    #[arg(global = true, long, conflicts_with = "prefer_ipv6")]
+    prefer_ipv4: bool,
+    /// Only connect to the marketplace over IPv6
+    #[arg(global = true, long)]
+    prefer_ipv6: bool,
+    /// Allow pre-release versions to be picked
+    #[arg(global = true, long, alias = "prerelease")]
+    pre_release: bool,
+    /// List the publishers matching the search instead of extensions
+    #[arg(global = true, long, conflicts_with_all = ["interactive", "from_file"])]
+    list_publishers: bool,
+    /// Don't send the target criterion, for galleries that don't know Microsoft.VisualStudio.Code
+    #[arg(global = true, long)]
+    no_target_filter: bool,
+    /// Pick the result with this publisher.name id instead of prompting for an index
+    #[arg(global = true, long, value_name = "ID", conflicts_with_all = ["interactive", "from_file"])]
+    select_by_id: Option<String>,
+    /// Fail instead of picking a near match when the search is a publisher.name id
+    #[arg(global = true, long)]
+    match_id_exact: bool,
+    /// Search a publisher.name id like any other text instead of looking it up exactly
+    #[arg(global = true, long)]
+    fuzzy: bool,
+    /// Also download the extensions of an extension pack, and the packs inside it
+    #[arg(global = true, long, conflicts_with_all = ["from_file", "auto_update"])]
+    expand_packs_recursively: bool,
+    /// Also download the extensions it depends on and the members of its pack, and install them first
+    #[arg(global = true, long, conflicts_with_all = ["from_file", "auto_update", "expand_packs_recursively", "since_version", "resolve_only"])]
+    with_dependencies: bool,
+    /// How deep nested extension packs are expanded
+    #[arg(
+        global = true,
+        long,
+        default_value_t = 5,
+        requires = "expand_packs_recursively"
+    )]
+    max_depth: usize,
+    /// Download every version newer than this one instead of only the latest, to update a mirror
+    #[arg(global = true, long, value_name = "VERSION", conflicts_with_all = ["from_file", "auto_update", "resolve_only", "expand_packs_recursively"])]
+    since_version: Option<String>,
+    /// Download this version of the extension instead of the latest one
+    #[arg(global = true, long, alias = "ext-version", value_name = "SEMVER", conflicts_with_all = ["from_file", "auto_update", "since_version"])]
+    pin: Option<String>,
+    /// Print the details of the extension and exit without downloading it
+    #[arg(global = true, long, alias = "print-metadata-only", conflicts_with_all = ["from_file", "auto_update", "since_version"])]
+    info: bool,
+    /// Print the results, the details or the saved path as json on stdout, everything else goes to stderr
+    #[arg(global = true, long)]
+    json: bool,
+    /// Print the resolved extension as json instead of downloading it
+    #[arg(global = true, long, conflicts_with_all = ["from_file", "list_publishers"])]
+    resolve_only: bool,
+    /// Update every extension installed in the editor to its latest version
+    #[arg(global = true, long, conflicts_with_all = ["from_file", "interactive", "list_publishers", "resolve_only", "remote"])]
+    auto_update: bool,
+    /// Only list the installed extensions that have a newer version, for --auto-update
+    #[arg(global = true, long)]
+    dry_run: bool,
+    /// Download every extension listed in a file, one search term or publisher.name[@version] per line
+    #[arg(global = true, long, conflicts_with = "interactive")]
+    from_file: Option<String>,
+    /// Install every extension of the batch in the editor instead of saving it to the output folder
+    #[arg(global = true, long, requires = "from_file")]
+    install: bool,
+    /// Finish the .part downloads left in this folder by --resume, the temporary folder by default
+    #[arg(global = true, long, value_name = "DIR", num_args = 0..=1)]
+    resume_all: Option<Option<String>>,
+    /// Write the gallery metadata of each saved file next to it as <file>.json
+    #[arg(global = true, long)]
+    metadata_sidecar: bool,
+    /// Query the gallery again and rewrite the metadata sidecars of this folder, without downloading
+    #[arg(global = true, long, value_name = "DIR")]
+    refresh_metadata: Option<String>,
+    /// Keep downloading the rest of a batch when an extension fails (default)
+    #[arg(global = true, long, conflicts_with = "fail_fast")]
+    keep_going: bool,
+    /// Stop a batch at the first extension that fails
+    #[arg(global = true, long)]
+    fail_fast: bool,
+    /// Wait this many milliseconds between the downloads of a batch, to avoid being rate limited
+    #[arg(global = true, long, value_name = "MS", default_value_t = 0)]
+    batch_delay: u64,
+    /// Print a json summary of the batch once it's done
+    #[arg(global = true, long, requires = "from_file")]
+    summary_json: bool,
+    /// Write the json summary of the batch to a file instead of printing it
+    #[arg(global = true, long, requires = "from_file")]
+    summary_file: Option<String>,
+    /// Keep interrupted downloads and continue them on the next run
+    #[arg(global = true, long)]
+    resume: bool,
+    /// Record the hash of each download and verify it matches on later downloads
+    #[arg(global = true, long)]
+    lockfile: Option<String>,
+    /// Verify the download against a SHA256SUMS style file listing the hash of each file name
+    #[arg(global = true, long, value_name = "PATH")]
+    checksum_file: Option<String>,
+    /// Check that the package.json of the download is the extension and version that was asked for
+    #[arg(global = true, long)]
+    validate_manifest_id: bool,
+    /// Skip extensions whose manifest doesn't contribute this, like debuggers, themes or languages
+    #[arg(
+        global = true,
+        long,
+        alias = "manifest-filter",
+        value_name = "CAPABILITY",
+        value_delimiter = ','
+    )]
+    requires: Vec<String>,
+    /// Retry the search with a smaller limit when it takes longer than this many seconds
+    #[arg(global = true, long)]
+    search_timeout: Option<u64>,
+    /// Give up on a download when no data arrives for this many seconds
+    #[arg(global = true, long, value_name = "SECONDS")]
+    stall_timeout: Option<u64>,
+    /// How many times a stalled download is continued before giving up
+    #[arg(global = true, long, default_value_t = 2, requires = "stall_timeout")]
+    stall_retries: u32,
+    /// Download the vsix over this many connections at once, each fetching a range of it
+    #[arg(global = true, long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=16))]
+    connections: u64,
+    /// How the wait before a retry is randomized, so many machines don't retry all at once
+    #[arg(global = true, long, value_enum, default_value_t = Jitter::Full)]
+    retry_jitter: Jitter,
+    /// Replace characters that aren't allowed in file names from the version
+    #[arg(global = true, long)]
+    normalize_version: bool,
+    /// Lowercase the publisher and extension names in file names
+    #[arg(global = true, long)]
+    lowercase_names: bool,
+    /// Show the colors of the themes contributed by the extension after downloading it
+    #[arg(global = true, long)]
+    theme_preview: bool,
+    /// The editor profile the extension is installed into
+    #[arg(global = true, long, value_name = "NAME")]
+    editor_profile: Option<String>,
+    /// Pass the profile even if the editor doesn't list it in its help
+    #[arg(global = true, long, requires = "editor_profile")]
+    no_profile_check: bool,
+    /// Only show the output of the editor when the install fails, on by default with --quiet
+    #[arg(global = true, long)]
+    capture_install_output: bool,
+    /// Install the extension on a remote machine over ssh instead of locally
+    #[arg(global = true, long, value_name = "USER@HOST")]
+    remote: Option<String>,
+    /// The program to use to install the extension on the remote machine
+    #[arg(
+        global = true,
+        long,
+        default_value = "code-server",
+        requires = "remote"
+    )]
+    remote_program: String,
+    /// Print the package.json of the extension after downloading it
+    #[arg(global = true, long, value_enum, num_args = 0..=1, default_missing_value = "summary")]
+    manifest: Option<ManifestFormat>,
+    /// The format the extension is saved in
+    #[arg(global = true, long, value_enum, default_value_t = OutputFormat::Vsix)]
+    output_format: OutputFormat,
+    /// What to do when the output file already exists
+    #[arg(global = true, long, value_enum, default_value_t = CollisionPolicy::Overwrite)]
+    on_collision: CollisionPolicy,
+    /// Don't check that the temporary folder has enough free space before downloading
+    #[arg(global = true, long)]
+    no_preflight_disk_space: bool,
+    /// The characters used to draw the progress bar
+    #[arg(global = true, long, value_enum, default_value_t = BarStyle::Ascii)]
+    progress_bar_style: BarStyle,
+    /// When to color the output, auto colors it on a terminal unless NO_COLOR is set
+    #[arg(global = true, long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+    /// Keep the progress bar a single color instead of going from red to green
+    #[arg(global = true, long)]
+    mono: bool,
+    /// When to make the errors red, follows --color by default
+    #[arg(global = true, long, value_enum, value_name = "WHEN")]
+    color_errors: Option<ColorChoice>,
+    /// Use symbols like ✓ and ✗ in the output, on by default with a UTF-8 locale
+    #[arg(global = true, long, conflicts_with = "no_emoji")]
+    emoji: bool,
+    /// Use ascii like [OK] and [X] in the output instead of symbols
+    #[arg(global = true, long)]
+    no_emoji: bool,
+    /// Also download the icon of the extension into this folder
+    #[arg(global = true, long, value_name = "DIR")]
+    download_icon: Option<String>,
+    /// Print the json body of the query on stderr before sending it
+    #[arg(global = true, long)]
+    dump_request: bool,
+    /// Write the raw response of the search to this file, it can be replayed with -a file://PATH
+    #[arg(global = true, long, value_name = "PATH")]
+    save_query_response: Option<String>,
+    /// Fail when the response of the search has fields this version doesn't know about
+    #[arg(global = true, long)]
+    strict_json: bool,
+    /// Copy the download url with --resolve-only, or else the saved path, to the clipboard
+    #[arg(global = true, long)]
+    clipboard: bool,
+    /// Hide the search results installed fewer than this many times
+    #[arg(global = true, long, value_name = "N")]
+    min_installs: Option<u64>,
+    /// Extra flags that exclude an extension from the results, unpublished ones always are
+    #[arg(global = true, long, value_enum, value_delimiter = ',')]
+    exclude_flags: Vec<RequestFlags>,
+    /// Only find extensions the gallery lists as working with this editor version or version range
+    #[arg(global = true, long, value_name = "VERSION")]
+    installation_target_version: Option<String>,
+    /// Which fields of the extensions the search is matched against
+    #[arg(
+        global = true,
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "text"
+    )]
+    search_fields: Vec<SearchField>,
+}
+
+// Each subcommand is a shortcut for the flags it sets, the other flags work with all of them
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the extensions matching the search without downloading anything
+    Search { search: String },
+    /// Download an extension into the output folder without installing it
+    Download {
+        /// The publisher.name id of the extension
+        id: String,
+    },
+    /// Download an extension and install it without asking
+    Install {
+        /// The publisher.name id of the extension
+        #[arg(required_unless_present = "recommended")]
+        id: Option<String>,
+        /// Install the recommendations of a workspace's .vscode/extensions.json instead, the current folder by default
+        #[arg(long, value_name = "PATH", num_args = 0..=1, conflicts_with = "id")]
+        recommended: Option<Option<String>>,
+    },
+    /// Print the details of an extension without downloading it
+    Info {
+        /// The publisher.name id of the extension
+        id: String,
+    },
+    /// List every published version of an extension
+    Versions {
+        /// The publisher.name id of the extension
+        id: String,
+    },
+    /// Update the extensions installed in the editor that have a newer version
+    Update,
+    /// List or clean the downloads kept in the cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// List the cached extensions with their size
+    List,
+    /// Remove cached extensions, all of them unless a limit is given
+    Clean {
+        /// Only remove the ones cached more than this many days ago
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u64>,
+        /// Remove the oldest ones until the cache takes at most this many megabytes
+        #[arg(long, value_name = "MB")]
+        max_size: Option<u64>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ApiPreset {
+    Vscode,
+    Openvsx,
+    Azuredevops,
+}
+
+impl ApiPreset {
+    fn url(self) -> &'static str {
+        match self {
+            ApiPreset::Vscode | ApiPreset::Azuredevops => gallery::DEFAULT_API_URL,
+            // Open VSX serves a copy of the marketplace api so the answers have the same shape
+            ApiPreset::Openvsx => openvsx::GALLERY_API_URL,
+        }
+    }
+
+    // Azure DevOps extensions live in the same gallery under a different target
+    fn target(self) -> &'static str {
+        match self {
+            ApiPreset::Vscode | ApiPreset::Openvsx => gallery::DEFAULT_TARGET,
+            ApiPreset::Azuredevops => "Microsoft.VisualStudio.Services",
+        }
+    }
+}
+
+impl Args {
+    // Only the flags that weren't typed are taken from the config, the defaults of clap don't count
+    fn apply_config(&mut self, matches: &ArgMatches) -> Result<(), Error> {
+        if self.no_config {
+            return Ok(());
+        }
+
+        let config = read_config(self.config.as_deref())?;
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+
+        if let (Some(api), true) = (config.api, unset("api") && unset("api_preset")) {
+            self.api = Some(api);
+        }
+        if let (Some(program), true) = (config.program, unset("program")) {
+            self.program = program;
+        }
+        if let (Some(output), true) = (config.output, unset("output")) {
+            self.output = output;
+        }
+        if let (Some(limit), true) = (config.limit, unset("limit")) {
+            self.limit = limit;
+        }
+        if let (Some(proxy), true) = (config.proxy, unset("proxy")) {
+            self.proxy = Some(proxy);
+        }
+        if let (Some(platform), None) = (config.target_platform, self.target_platform) {
+            self.target_platform = Some(
+                platform
+                    .parse()
+                    .map_err(|_| Error::UnknownPlatform(platform.clone()))?,
+            );
+        }
+
+        Ok(())
+    }
+
+    // The subcommands only set the flags the same run would need without them
+    fn apply_command(&mut self) -> Result<(), Error> {
+        let id = match &self.command {
+            None => return Ok(()),
+            Some(Command::Search { search }) => {
+                self.search = Some(search.clone());
+                return Ok(());
+            }
+            Some(Command::Install { id: None, .. }) => {
+                self.install = true;
+                return Ok(());
+            }
+            Some(Command::Update) => {
+                self.auto_update = true;
+                return Ok(());
+            }
+            Some(Command::Cache { .. }) => return Ok(()),
+            Some(
+                Command::Download { id }
+                | Command::Install { id: Some(id), .. }
+                | Command::Info { id }
+                | Command::Versions { id },
+            ) => id.clone(),
+        };
+
+        if !is_extension_id(&id) {
+            return Err(Error::NotAnId(id));
+        }
+
+        self.search = Some(id);
+        self.match_id_exact = true;
+
+        match self.command {
+            Some(Command::Download { .. }) => {
+                self.yes = true;
+                self.no_install = true;
+            }
+            Some(Command::Install { .. }) => self.yes = true,
+            Some(Command::Info { .. }) => self.info = true,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // An id is looked up by name and picked without prompting, so the same command always gets the same extension
+    fn exact_id(&self, search: &str) -> bool {
+        is_extension_id(search) && (self.match_id_exact || !(self.fuzzy || self.list_publishers))
+    }
+
+    // The selector needs a terminal to read the keys from, pipes keep getting the typed index
+    fn arrow_keys(&self) -> bool {
+        !self.type_index && io::stdin().is_terminal() && io::stdout().is_terminal()
+    }
+
+    // Machine readable modes keep the banners out of their output too
+    fn banners(&self) -> bool {
+        !(self.quiet || self.resolve_only || self.json)
+    }
+
+    fn bar_colors(&self) -> bool {
+        !self.mono && self.color.enabled(io::stdout())
+    }
+
+    fn capture_install_output(&self) -> bool {
+        self.capture_install_output || self.quiet
+    }
+
+    fn api_url(&self) -> &str {
+        match (&self.api, self.registry) {
+            (Some(api), _) => api,
+            (None, Some(Registry::Openvsx)) => openvsx::DEFAULT_API_URL,
+            (None, _) => self.api_preset.url(),
+        }
+    }
+
+    fn registry(&self) -> Registry {
+        self.registry
+            .unwrap_or_else(|| Registry::detect(self.api_url()))
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SearchField {
+    Text,
+    Name,
+    Displayname,
+    Tag,
+    Category,
+    Publisher,
+    Publisherdisplayname,
+}
+
+impl SearchField {
+    fn filter_type(self) -> FilterType {
+        match self {
+            SearchField::Text => FilterType::SearchText,
+            SearchField::Name => FilterType::ExtensionName,
+            SearchField::Displayname => FilterType::DisplayName,
+            SearchField::Tag => FilterType::Tag,
+            SearchField::Category => FilterType::Category,
+            SearchField::Publisher => FilterType::PublisherName,
+            SearchField::Publisherdisplayname => FilterType::PublisherDisplayName,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Vsix,
+    Tar,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DefaultAnswer {
+    Yes,
+    No,
+}
+
+impl DefaultAnswer {
+    fn is_yes(self) -> bool {
+        self == DefaultAnswer::Yes
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum CollisionPolicy {
+    Overwrite,
+    Renumber,
+    Skip,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ManifestFormat {
+    Summary,
+    Raw,
 }
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    if let Err(error) = get_vsix().await {
-        eprintln!("{}", error);
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+
+    if let Err(error) = args
+        .apply_config(&matches)
+        .and_then(|_| args.apply_command())
+    {
+        print_error(&error);
+        return ExitCode::FAILURE;
+    }
+
+    set_emoji(if args.emoji {
+        true
+    } else if args.no_emoji {
+        false
+    } else {
+        unicode_supported()
+    });
+
+    set_error_color(
+        args.color_errors
+            .unwrap_or(args.color)
+            .enabled(io::stderr()),
+    );
+
+    // Only the json is left on stdout so it can be piped
+    if args.suppress_success_output || args.json {
+        if let Err(error) = redirect_stdout() {
+            print_error(&error);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(platform) = args.target_platform {
+        set_target_platform(platform);
+    }
+
+    if args.platform_from_program {
+        match program_arch(&args.program) {
+            Some(arch) => set_program_arch(arch),
+            None => eprintln!(
+                "Couldn't get the architecture of {}, using the one of this machine",
+                args.program
+            ),
+        }
+    }
+
+    if args.engine.as_deref() == Some("auto") {
+        args.engine = program_version(&args.program);
+
+        if args.engine.is_none() {
+            eprintln!(
+                "Couldn't get the version of {}, every version of the extensions can be picked",
+                args.program
+            );
+        }
+    }
+
+    let result = get_vsix(&args).await;
+
+    if args.verbose {
+        print_telemetry();
+    }
+
+    if let Err(error) = result {
+        if args.progress_json {
+            ProgressEvent::Error {
+                message: error.to_string(),
+            }
+            .emit();
+        }
+
+        print_error(&error);
         ExitCode::FAILURE
     } else {
         ExitCode::SUCCESS
     }
 }
 
-async fn get_vsix() -> Result<(), Error> {
-    let args = Args::parse();
+const WRITE_BUFFER_SIZE: usize = 256 * 1024;
+const PROGRESS_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
 
-    let resp = reqwest::Client::new()
-        .post(format!("{}?api-version={}", &args.api, &args.api_version))
-        .header(CONTENT_TYPE, "application/json")
-        .json(&RequestOptions {
-            filters: vec![RequestFilters {
-                pageNumber: 1,
-                pageSize: args.limit,
-                criteria: vec![
-                    RequestCriteria {
-                        filterType: FilterType::SearchText as i8,
-                        value: args.search.clone(),
-                    },
-                    RequestCriteria {
-                        filterType: FilterType::Target as i8,
-                        value: "Microsoft.VisualStudio.Code".to_string(),
-                    },
-                    RequestCriteria {
-                        filterType: FilterType::ExcludeWithFlags as i8,
-                        value: (RequestFlags::Unpublished as i16).to_string(),
-                    },
-                ],
-            }],
-        })
-        .send()
-        .await
-        .map_err(Error::ReqwestDns)?;
+async fn get_vsix(args: &Args) -> Result<(), Error> {
+    if let Some(dir) = &args.serve {
+        return serve(dir, &args.serve_host, args.port).await;
+    }
 
-    let answer = resp
-        .json::<ExpectedAnswer>()
-        .await
-        .map_err(Error::JsonParse)?;
+    if let Some(Command::Cache { action }) = &args.command {
+        return manage_cache(args, action);
+    }
+
+    if !args.verify_only.is_empty() {
+        return verify_files(args, &args.verify_only);
+    }
+
+    let deadline = args
+        .deadline
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+
+    let client = build_client(args)?;
+
+    if args.probe {
+        return probe(&client, args, deadline).await;
+    }
+
+    if args.auto_update {
+        return auto_update(&client, args, deadline).await;
+    }
+
+    if let Some(list) = &args.from_file {
+        return batch_download(&client, args, list, deadline).await;
+    }
+
+    if let Some(Command::Install {
+        recommended: Some(path),
+        ..
+    }) = &args.command
+    {
+        return install_recommended(&client, args, path.as_deref().unwrap_or("."), deadline).await;
+    }
+
+    if let Some(dir) = &args.refresh_metadata {
+        return refresh_metadata(&client, args, dir, deadline).await;
+    }
+
+    if let Some(dir) = &args.resume_all {
+        let dir = dir
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(env::temp_dir);
+        return resume_all(&client, args, &dir, deadline).await;
+    }
+
+    let search = args.search.as_deref().unwrap_or_default();
+
+    let mut extensions = query_extensions(&client, args, search, deadline).await?;
+
+    if let Some(min_installs) = args.min_installs {
+        let before = extensions.len();
+        extensions.retain(|extension| extension.installs() >= min_installs);
+
+        if args.banners() && extensions.len() < before {
+            println!(
+                "Hid {} extension(s) with fewer than {} installs",
+                before - extensions.len(),
+                min_installs
+            );
+        }
+    }
+
+    if args.list_publishers {
+        return list_publishers(&extensions, search, args.banners());
+    }
+
+    if let Some(Command::Search { .. }) = args.command {
+        if extensions.is_empty() {
+            return Err(Error::Search(search.to_string()));
+        }
+
+        if args.json {
+            return print_search_json(args, &extensions);
+        }
+
+        print_extensions(&extensions, None, args);
+        return Ok(());
+    }
 
-    if answer.results[0].extensions.is_empty() {
-        return Err(Error::Search(args.search.clone()));
+    if extensions.is_empty() {
+        return Err(Error::Search(search.to_string()));
     } else {
-        let extension = if answer.results[0].extensions.len() > 1 {
-            println!("Found {} extensions", &answer.results[0].extensions.len());
-            println!();
+        let history = if args.no_history {
+            BTreeMap::new()
+        } else {
+            read_history()
+        };
+        let remembered = history.get(&history_key(search)).map(String::as_str);
 
-            for (i, extension) in answer.results[0].extensions.iter().enumerate() {
-                let publisher_name = &extension.publisher.publisherName;
-                let extension_name = &extension.extensionName;
-                let version = &extension.versions[0].version;
+        let extension = if let Some(id) = &args.select_by_id {
+            let choice = extensions
+                .iter()
+                .position(|extension| extension.id().eq_ignore_ascii_case(id))
+                .ok_or_else(|| Error::NotInResults(id.clone(), search.to_string()))?;
 
-                println!(
-                    "[{}] : {} by {} v{}",
-                    i + 1,
-                    extension_name,
-                    publisher_name,
-                    version
-                );
+            select_extension(extensions, choice + 1)?
+        } else if args.exact_id(search) {
+            exact_match(extensions, search)?
+        } else if args.interactive {
+            interactive_search(&client, args, extensions, remembered, deadline).await?
+        } else if args.yes && extensions.len() > 1 {
+            // The exact id wins, then the remembered pick, then the first result
+            let choice = extensions
+                .iter()
+                .position(|extension| extension.id().eq_ignore_ascii_case(search))
+                .or(default_index(&extensions, remembered))
+                .unwrap_or(0);
+
+            select_extension(extensions, choice + 1)?
+        } else if extensions.len() > 1 && args.arrow_keys() {
+            let labels: Vec<String> = extensions
+                .iter()
+                .map(|extension| extension_label(extension, args))
+                .collect();
+
+            match select(&labels, default_index(&extensions, remembered))? {
+                Some(choice) => select_extension(extensions, choice + 1)?,
+                None => return Err(Error::Cancelled()),
             }
+        } else if extensions.len() > 1 {
+            let default = default_index(&extensions, remembered);
+            print_extensions(&extensions, default, args);
 
-            println!();
+            let choice = loop {
+                let line = input(index_prompt(default))?;
 
-            let choice: usize =
-                input("Input the index of the extension you want to download: ".to_owned())?
-                    .trim()
-                    .parse()
-                    .map_err(Error::ParseInt)?;
+                // read_line gives back nothing at all, not even a newline, once stdin is closed
+                if line.is_empty() {
+                    return Err(Error::InputClosed());
+                }
 
-            println!();
+                println!();
+
+                match (line.trim(), default) {
+                    ("", Some(default)) => break default + 1,
+                    (line, _) => match parse_index(line, extensions.len()) {
+                        Ok(choice) => break choice,
+                        Err(error) => {
+                            println!("{}", error);
+                            println!();
+                        }
+                    },
+                }
+            };
+
+            select_extension(extensions, choice)?
+        } else {
+            if args.banners() {
+                println!("Found 1 extension");
+            }
+            select_extension(extensions, 1)?
+        };
 
-            match &answer.results[0].extensions.get(choice - 1) {
-                Some(i) => i,
-                None => return Err(Error::IndexOutOfBound()),
+        if !args.no_history {
+            if let Err(error) = save_history(history, &history_key(search), &extension.id()) {
+                eprintln!("Couldn't remember the selection: {}", error);
             }
+        }
+
+        if let Some(Command::Versions { .. }) = args.command {
+            print_versions(&extension);
+            return Ok(());
+        }
+
+        let extension = compatible_versions(args, extension)?;
+
+        if let Some(since) = &args.since_version {
+            return download_since(&client, args, &extension, since, deadline).await;
+        }
+
+        let index = match &args.pin {
+            Some(version) => requested_version_index(&extension, version)?,
+            None => platform_version_index(&extension, args),
+        };
+
+        if args.resolve_only {
+            return resolve(&client, args, &extension, index, deadline).await;
+        }
+
+        if args.info {
+            return print_info(args, &extension, index);
+        }
+
+        print_metadata(&extension, index);
+
+        if args.all_platforms {
+            return download_all_platforms(&client, args, &extension, index, deadline).await;
+        }
+
+        let members = if args.expand_packs_recursively {
+            expand_pack(&client, args, &extension, index, deadline).await?
         } else {
-            println!("Found 1 extension");
-            &answer.results[0].extensions[0]
+            Vec::new()
         };
 
-        let publisher_name = &extension.publisher.publisherName;
-        let extension_name = &extension.extensionName;
+        if !args.yes && !confirm("Do you want to continue?", args.default_answer.is_yes())? {
+            return Ok(());
+        }
+
+        let (tmp_path, filename) =
+            download_extension(&client, args, &extension, index, deadline).await?;
 
-        let description = match &extension.shortDescription {
-            Some(desc) => desc,
-            _ => "",
+        let install = if args.no_install {
+            false
+        } else {
+            args.yes
+                || confirm(
+                    "Do you want me to install the extension you downloaded?",
+                    args.default_answer.is_yes(),
+                )?
         };
 
-        let target_platform = get_target_platform();
+        let dependencies = if args.with_dependencies {
+            download_dependencies(
+                &client, args, &extension, index, &tmp_path, install, deadline,
+            )
+            .await?
+        } else {
+            Vec::new()
+        };
 
-        let index = &extension
-            .versions
-            .iter()
-            .position(|r| match r.targetPlatform {
-                Some(t) => t == target_platform,
-                None => false,
-            });
+        for dependency in dependencies {
+            if install {
+                install_download(
+                    args,
+                    &dependency.extension,
+                    dependency.index,
+                    &dependency.tmp_path,
+                )?;
+            } else {
+                save_download(
+                    args,
+                    &dependency.extension,
+                    dependency.index,
+                    dependency.tmp_path,
+                    &dependency.filename,
+                )?;
+            }
+        }
+
+        // The members were listed before the confirmation, they follow the answer given for the pack
+        for (member, member_index) in &members {
+            batch_pause(args, 1).await;
+            println!();
+            println!("Downloading {} from the pack", member.id());
+
+            let (member_path, member_filename) =
+                download_extension(&client, args, member, *member_index, deadline).await?;
+
+            if install {
+                install_download(args, member, *member_index, &member_path)?;
+            } else {
+                save_download(args, member, *member_index, member_path, &member_filename)?;
+            }
+        }
 
-        let index = match index {
-            Some(i) => i,
-            None => &0,
+        let path = if install {
+            install_download(args, &extension, index, &tmp_path)?;
+            tmp_path
+        } else {
+            save_download(args, &extension, index, tmp_path, &filename)?
         };
 
-        let version = &extension.versions[*index].version;
+        if args.progress_json {
+            ProgressEvent::Done { path: &path }.emit();
+        }
 
-        println!("{}:", extension_name);
-        println!("{}", description);
-        println!();
-        println!("\tPublisher: {}", publisher_name);
-        println!("\tVersion: {}", version);
-        println!("\tFlags: {}", &extension.flags);
-        println!("\tLast updated: {}", &extension.lastUpdated);
-        println!("\tPublished date: {}", &extension.publishedDate);
-        println!("\tRelease date: {}", &extension.releaseDate);
-        println!();
+        if args.json {
+            print_download_json(&extension, index, &path)?;
+        }
 
-        let confirm = input("Do you want to continue? [Y/n]: ".to_owned())?
-            .trim()
-            .to_lowercase();
-
-        match confirm.as_str() {
-            "y" => {
-                let download_index = &extension.versions[*index]
-                    .files
-                    .iter()
-                    .position(|r| r.assetType == "Microsoft.VisualStudio.Services.VSIXPackage")
-                    .ok_or(Error::IndexOutOfBound())?;
-
-                let download_url =
-                    match Url::parse(&extension.versions[*index].files[*download_index].source) {
-                        Ok(parsed) => Ok(parsed),
-                        Err(_) => Err(Error::UrlParse()),
-                    }?;
-
-                let resp = reqwest::get(download_url)
-                    .await
-                    .map_err(Error::ReqwestDns)?;
+        if args.clipboard {
+            copy_to_clipboard(&path)?;
+            println!("{} Copied {} to the clipboard", Glyph::Ok, path);
+        }
+    }
 
-                let total_size = resp.content_length().ok_or(Error::ReqwestLength())?;
+    Ok(())
+}
 
-                let total_size_format = format_size(total_size as usize);
+fn install_download(
+    args: &Args,
+    extension: &Extension,
+    index: usize,
+    tmp_path: &str,
+) -> Result<(), Error> {
+    let version = &extension.versions[index].version;
 
-                println!("Downloading {}...", total_size_format);
+    if let Some(remote) = &args.remote {
+        install_extension_remote(tmp_path.to_string(), remote, &args.remote_program)?;
+        println!("{} Installed {}@{}", Glyph::Ok, extension.id(), version);
+        return Ok(());
+    }
 
-                let filename = format!("{}.{}-{}.vsix", publisher_name, extension_name, version);
-                let tmp_path = format!("{}/{}", env::temp_dir().display(), &filename);
+    let installed = installed_version(
+        &args.program,
+        args.editor_profile.as_deref(),
+        &extension.id(),
+    );
 
-                let mut file = File::create(&tmp_path).map_err(Error::FileWrite)?;
-                let mut stream = resp.bytes_stream();
+    // Reinstalling the same version would only make the editor ask for a restart
+    if installed.as_ref() == Some(version) {
+        println!(
+            "{} {} is already at version {}",
+            Glyph::Ok,
+            extension.id(),
+            version
+        );
+        return Ok(());
+    }
 
-                let mut progress = 0;
-                let start = Instant::now();
-                while let Some(byte) = stream.next().await {
-                    let chunk = byte.map_err(Error::ReqwestDns)?;
-                    progress += chunk.len();
+    install_extension(
+        tmp_path.to_string(),
+        args.program.clone(),
+        args.editor_profile.as_deref(),
+        !args.no_profile_check,
+        args.capture_install_output(),
+    )?;
+    println!("{} Installed {}@{}", Glyph::Ok, extension.id(), version);
 
-                    let progress_format = format_size(progress);
+    Ok(())
+}
 
-                    let percentage: f64 = (progress as f64 / total_size as f64) * 100.0;
+// Walks the extension packs depth first and prints the tree. Each id is expanded once,
+// so a pack that ends up including itself is reported instead of looping forever
+async fn expand_pack(
+    client: &reqwest::Client,
+    args: &Args,
+    root: &Extension,
+    index: usize,
+    deadline: Option<Instant>,
+) -> Result<Vec<(Extension, usize)>, Error> {
+    let mut visited = HashSet::from([root.id().to_lowercase()]);
+    let mut path = vec![root.id().to_lowercase()];
+    let mut members = Vec::new();
 
-                    let elapsed = if start.elapsed().as_secs() <= 0 {
-                        1
-                    } else {
-                        start.elapsed().as_secs()
-                    } as usize;
+    let mut stack: Vec<(String, usize)> = root.versions[index]
+        .pack_members()
+        .into_iter()
+        .rev()
+        .map(|id| (id, 1))
+        .collect();
 
-                    let download_speed = (progress - chunk.len()) / elapsed;
+    println!("Extension pack:");
+    println!();
+    println!("\t{}", root.id());
 
-                    print!(
-                        "{}{}\r{}% [{}{}] {}",
-                        Ansi::CursorUp.to_string(),
-                        Ansi::ClearLine.to_string(),
-                        percentage as usize,
-                        {
-                            let mut bar = "=".repeat(percentage as usize / 3);
-                            if percentage < 100.0 {
-                                bar += ">"
-                            }
-                            bar
-                        },
-                        " ".repeat(100 / 3 - percentage as usize / 3),
-                        progress_format,
-                    );
+    while let Some((id, depth)) = stack.pop() {
+        let indent = "\t".repeat(depth + 1);
+        let key = id.to_lowercase();
+        path.truncate(depth);
 
-                    print!(
-                        "{}\r{}{}/s",
-                        Ansi::CursorDown.to_string(),
-                        Ansi::ClearLine.to_string(),
-                        format_size(download_speed)
-                    );
+        if path.contains(&key) {
+            println!("{}{} (cycle)", indent, id);
+            eprintln!(
+                "Warning: {} includes itself through {}, not expanding it again",
+                id,
+                path.join(" -> ")
+            );
+            continue;
+        }
 
-                    std::io::stdout().flush().map_err(Error::Flush)?;
-                    file.write_all(&chunk).map_err(Error::FileWrite)?;
-                }
+        if !visited.insert(key.clone()) {
+            println!("{}{} (already listed)", indent, id);
+            continue;
+        }
 
-                println!("\nDownload successful.");
+        let extensions = query_extensions(client, args, &id, deadline).await?;
+        let choice = match extensions
+            .iter()
+            .position(|extension| extension.id().eq_ignore_ascii_case(&id))
+        {
+            Some(choice) => choice,
+            None => {
+                println!("{}{} (not found)", indent, id);
+                continue;
+            }
+        };
 
-                let choice = input(
-                    "Do you want me to install the extension you downloaded? [Y/n]: ".to_owned(),
-                )?
-                .trim()
-                .to_lowercase();
+        // A member that can't be downloaded is left out, the rest of the pack still is
+        let member = match select_extension(extensions, choice + 1) {
+            Ok(member) => member,
+            Err(_) => {
+                println!("{}{} (not available)", indent, id);
+                continue;
+            }
+        };
+        let member = match compatible_versions(args, member) {
+            Ok(member) => member,
+            Err(_) => {
+                println!("{}{} (incompatible)", indent, id);
+                continue;
+            }
+        };
+        let member_index = platform_version_index(&member, args);
+        let nested = member.versions[member_index].pack_members();
 
-                match choice.as_str() {
-                    "y" => install_extension(tmp_path, args.program),
-                    _ => {
-                        let path = format!("{}/{}", &args.output, &filename);
-                        move_to(tmp_path, path)
-                    }
-                }?;
+        println!(
+            "{}{} v{}",
+            indent,
+            member.id(),
+            member.versions[member_index].version
+        );
+
+        if !nested.is_empty() {
+            if depth >= args.max_depth {
+                println!("{}\t... (--max-depth reached)", indent);
+            } else {
+                path.push(key);
+                stack.extend(nested.into_iter().rev().map(|id| (id, depth + 1)));
             }
-            _ => return Ok(()),
         }
+
+        members.push((member, member_index));
+    }
+
+    println!();
+
+    Ok(members)
+}
+
+// Saves every build of the picked version side by side, for mirrors that serve several platforms
+async fn download_all_platforms(
+    client: &reqwest::Client,
+    args: &Args,
+    extension: &Extension,
+    index: usize,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let version = &extension.versions[index].version;
+    let builds: Vec<usize> = extension
+        .versions
+        .iter()
+        .enumerate()
+        .filter(|(_, candidate)| &candidate.version == version)
+        .map(|(i, _)| i)
+        .collect();
+
+    println!("Builds of v{}:", version);
+    for i in &builds {
+        println!(
+            "\t{}",
+            extension.versions[*i]
+                .targetPlatform
+                .unwrap_or(TargetPlatform::Universal)
+        );
+    }
+    println!();
+
+    if !args.yes && !confirm("Do you want to continue?", args.default_answer.is_yes())? {
+        return Ok(());
+    }
+
+    let mut paths = Vec::new();
+
+    for (n, i) in builds.iter().enumerate() {
+        batch_pause(args, n).await;
+
+        let (tmp_path, filename) =
+            download_extension(client, args, extension, *i, deadline).await?;
+        paths.push((*i, save_download(args, extension, *i, tmp_path, &filename)?));
+    }
+
+    if args.json {
+        let outputs: Vec<DownloadOutput> = paths
+            .iter()
+            .map(|(i, path)| DownloadOutput {
+                metadata: Metadata::new(extension, *i),
+                path,
+            })
+            .collect();
+
+        let json = serde_json::to_string_pretty(&outputs)
+            .map_err(|error| Error::InvalidJson("the downloads".to_string(), error))?;
+        print_output(&json);
     }
 
     Ok(())
 }
+
+struct Dependency {
+    extension: Extension,
+    index: usize,
+    tmp_path: String,
+    filename: String,
+}
+
+// What an extension pulls in, read from its vsix and from the gallery properties
+fn dependency_ids(version: &Versions, tmp_path: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+
+    [
+        manifest_dependencies(tmp_path).unwrap_or_default(),
+        version.dependencies(),
+        version.pack_members(),
+    ]
+    .concat()
+    .into_iter()
+    .filter(|id| seen.insert(id.to_lowercase()))
+    .collect()
+}
+
+// Downloads the dependencies and pack members of the extension, then theirs once their own vsix can
+// be read. Every extension comes after what it depends on so installing in order works, the ones
+// the editor already has aren't downloaded when installing. The list is confirmed before any is kept
+async fn download_dependencies(
+    client: &reqwest::Client,
+    args: &Args,
+    root: &Extension,
+    index: usize,
+    tmp_path: &str,
+    install: bool,
+    deadline: Option<Instant>,
+) -> Result<Vec<Dependency>, Error> {
+    let mut visited = HashSet::from([root.id().to_lowercase()]);
+    let mut dependencies = Vec::new();
+
+    // Each level waits for its ids to be done before its own extension is listed
+    let mut ids: Vec<String> = dependency_ids(&root.versions[index], tmp_path);
+    ids.reverse();
+    let mut stack: Vec<(Vec<String>, Option<Dependency>)> = vec![(ids, None)];
+
+    println!("Dependencies:");
+    println!();
+    println!("\t{}", root.id());
+
+    while let Some((ids, _)) = stack.last_mut() {
+        let Some(id) = ids.pop() else {
+            if let Some((_, Some(dependency))) = stack.pop() {
+                dependencies.push(dependency);
+            }
+            continue;
+        };
+
+        let indent = "\t".repeat(stack.len() + 1);
+
+        if !visited.insert(id.to_lowercase()) {
+            println!("{}{} (already listed)", indent, id);
+            continue;
+        }
+
+        if install {
+            if let Some(version) =
+                installed_version(&args.program, args.editor_profile.as_deref(), &id)
+            {
+                println!("{}{} v{} (installed)", indent, id, version);
+                continue;
+            }
+        }
+
+        let extensions = query_extensions(client, args, &id, deadline).await?;
+        let choice = match extensions
+            .iter()
+            .position(|extension| extension.id().eq_ignore_ascii_case(&id))
+        {
+            Some(choice) => choice,
+            None => {
+                println!("{}{} (not found)", indent, id);
+                continue;
+            }
+        };
+
+        let extension = compatible_versions(args, select_extension(extensions, choice + 1)?)?;
+        let index = platform_version_index(&extension, args);
+        println!(
+            "{}{} v{}",
+            indent,
+            extension.id(),
+            extension.versions[index].version
+        );
+
+        let (tmp_path, filename) =
+            download_extension(client, args, &extension, index, deadline).await?;
+
+        let mut ids = dependency_ids(&extension.versions[index], &tmp_path);
+        ids.reverse();
+        stack.push((
+            ids,
+            Some(Dependency {
+                extension,
+                index,
+                tmp_path,
+                filename,
+            }),
+        ));
+    }
+
+    println!();
+
+    if dependencies.is_empty() {
+        return Ok(dependencies);
+    }
+
+    println!("{} first:", if install { "Installing" } else { "Saving" });
+    for dependency in &dependencies {
+        println!(
+            "\t{} v{}",
+            dependency.extension.id(),
+            dependency.extension.versions[dependency.index].version
+        );
+    }
+
+    if !args.yes && !confirm("Do you want to continue?", args.default_answer.is_yes())? {
+        for dependency in &dependencies {
+            fs::remove_file(&dependency.tmp_path).map_err(Error::FileDelete)?;
+        }
+        return Ok(Vec::new());
+    }
+
+    Ok(dependencies)
+}
+
+// Sleeps for --batch-delay before every item of a batch but the first one
+async fn batch_pause(args: &Args, item: usize) {
+    if item > 0 && args.batch_delay > 0 {
+        tokio::time::sleep(Duration::from_millis(args.batch_delay)).await;
+    }
+}
+
+// Downloads every extension listed in the file without prompting, one search term or id per line
+async fn batch_download(
+    client: &reqwest::Client,
+    args: &Args,
+    list: &str,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let content = fs::read_to_string(list).map_err(Error::FileRead)?;
+    let items: Vec<&str> = content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    run_batch(client, args, &items, deadline).await
+}
+
+// The path can be the workspace folder or the extensions.json itself
+async fn install_recommended(
+    client: &reqwest::Client,
+    args: &Args,
+    path: &str,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let path = Path::new(path);
+    let path = if path.is_dir() {
+        path.join(".vscode").join("extensions.json")
+    } else {
+        path.to_path_buf()
+    };
+
+    let content = fs::read_to_string(&path).map_err(Error::FileRead)?;
+    let workspace: Value = serde_json::from_str(&strip_jsonc(&content))
+        .map_err(|error| Error::InvalidJson(path.display().to_string(), error))?;
+
+    let ids = |key: &str| -> Vec<String> {
+        workspace[key]
+            .as_array()
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| id.as_str())
+                    .map(|id| id.trim().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let unwanted = ids("unwantedRecommendations");
+    let mut items: Vec<String> = Vec::new();
+
+    for id in ids("recommendations") {
+        if unwanted.iter().any(|other| other.eq_ignore_ascii_case(&id)) {
+            println!("Skipping {}, the workspace marks it as unwanted", id);
+        } else if !items.iter().any(|other| other.eq_ignore_ascii_case(&id)) {
+            items.push(id);
+        }
+    }
+
+    if items.is_empty() {
+        println!("{} doesn't recommend any extension", path.display());
+        return Ok(());
+    }
+
+    println!(
+        "Installing the {} extension(s) recommended by {}",
+        items.len(),
+        path.display()
+    );
+    println!();
+
+    let items: Vec<&str> = items.iter().map(String::as_str).collect();
+    run_batch(client, args, &items, deadline).await
+}
+
+async fn run_batch(
+    client: &reqwest::Client,
+    args: &Args,
+    items: &[&str],
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let mut results = Vec::new();
+    let mut downloaded = HashSet::new();
+    let mut fatal = None;
+
+    for (i, item) in items.iter().enumerate() {
+        batch_pause(args, i).await;
+        println!("[{}/{}] {}", i + 1, items.len(), item);
+
+        let start = Instant::now();
+        let mut result = BatchResult::new(item);
+
+        match batch_item(client, args, item, &mut downloaded, &mut result, deadline).await {
+            Ok(()) => {
+                if let (Some(path), true) = (&result.path, args.progress_json) {
+                    ProgressEvent::Done { path }.emit();
+                }
+            }
+            Err(error) => {
+                print_error(&error);
+                result.status = BatchStatus::Failed;
+                result.error = Some(error.to_string());
+
+                if args.fail_fast {
+                    fatal = Some(error);
+                }
+            }
+        }
+
+        result.duration = start.elapsed().as_secs_f64();
+        results.push(result);
+
+        println!();
+
+        if fatal.is_some() {
+            break;
+        }
+    }
+
+    let failures: Vec<&BatchResult> = results
+        .iter()
+        .filter(|result| result.status == BatchStatus::Failed)
+        .collect();
+    let duplicates = results
+        .iter()
+        .filter(|result| result.status == BatchStatus::Duplicate)
+        .count();
+    let filtered: Vec<&BatchResult> = results
+        .iter()
+        .filter(|result| result.status == BatchStatus::Filtered)
+        .collect();
+
+    println!(
+        "{} {} of {} extensions",
+        if args.install {
+            "Installed"
+        } else {
+            "Downloaded"
+        },
+        results.len() - failures.len() - duplicates - filtered.len(),
+        items.len()
+    );
+
+    if duplicates > 0 {
+        println!("Skipped {} duplicate(s)", duplicates);
+    }
+
+    if !filtered.is_empty() {
+        println!("Filtered:");
+        for result in &filtered {
+            println!(
+                "\t{} {}: {}",
+                Glyph::Failed,
+                result.requested,
+                result.error.as_deref().unwrap_or_default()
+            );
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("Failed:");
+        for result in &failures {
+            println!(
+                "\t{} {}: {}",
+                Glyph::Failed,
+                result.requested,
+                result.error.as_deref().unwrap_or_default()
+            );
+        }
+    }
+
+    if args.summary_json || args.json || args.summary_file.is_some() {
+        let summary = serde_json::to_string_pretty(&results)
+            .map_err(|error| Error::InvalidJson("the summary".to_string(), error))?;
+
+        match &args.summary_file {
+            Some(path) => fs::write(path, summary).map_err(Error::FileWrite)?,
+            None => print_output(&summary),
+        }
+    }
+
+    match fatal {
+        Some(error) => Err(error),
+        None if failures.is_empty() => Ok(()),
+        None => Err(Error::Batch(failures.len())),
+    }
+}
+
+// Each .part file comes with a sidecar that remembers its urls, so no search is needed
+async fn resume_all(
+    client: &reqwest::Client,
+    args: &Args,
+    dir: &Path,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let mut filenames: Vec<String> = fs::read_dir(dir)
+        .map_err(Error::FileRead)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .strip_suffix(".part")
+                .map(str::to_string)
+        })
+        .collect();
+    filenames.sort();
+
+    if filenames.is_empty() {
+        println!("No partial download in {}", dir.display());
+        return Ok(());
+    }
+
+    let mut failures = Vec::new();
+
+    for (i, filename) in filenames.iter().enumerate() {
+        println!("[{}/{}] {}", i + 1, filenames.len(), filename);
+
+        if let Err(error) = resume_part(client, args, dir, filename, deadline).await {
+            print_error(&error);
+            failures.push((filename, error));
+        }
+
+        println!();
+    }
+
+    println!(
+        "Finished {} of {} partial downloads",
+        filenames.len() - failures.len(),
+        filenames.len()
+    );
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    println!("Failed:");
+    for (filename, error) in &failures {
+        println!("\t{} {}: {}", Glyph::Failed, filename, error);
+    }
+
+    Err(Error::ResumeAll(failures.len()))
+}
+
+async fn resume_part(
+    client: &reqwest::Client,
+    args: &Args,
+    dir: &Path,
+    filename: &str,
+    deadline: Option<Instant>,
+) -> Result<String, Error> {
+    let sidecar: PartialSidecar = fs::read_to_string(dir.join(format!("{}.part.json", filename)))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+
+    if sidecar.urls.is_empty() {
+        return Err(Error::UnknownPartial(filename.to_string()));
+    }
+
+    let (tmp_path, sha256) =
+        download_file(client, args, &sidecar.urls, filename, Some(dir), deadline).await?;
+
+    if let Err(error) = verify_vsix(&tmp_path) {
+        fs::remove_file(&tmp_path).map_err(Error::FileDelete)?;
+        return Err(Error::CorruptDownload(
+            filename.to_string(),
+            error.to_string(),
+        ));
+    }
+
+    if let Some(checksum_file) = &args.checksum_file {
+        if let Err(error) = check_checksum_file(checksum_file, filename, &sha256) {
+            fs::remove_file(&tmp_path).map_err(Error::FileDelete)?;
+            return Err(error);
+        }
+    }
+
+    save_to_output(args, tmp_path, filename)
+}
+
+// Folders are checked for the vsix files they directly contain
+fn verify_files(args: &Args, paths: &[String]) -> Result<(), Error> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if !Path::new(path).is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+
+        let mut vsix: Vec<String> = fs::read_dir(path)
+            .map_err(Error::FileRead)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "vsix")
+            })
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        vsix.sort();
+        files.append(&mut vsix);
+    }
+
+    let mut failures = 0;
+
+    for file in &files {
+        match verify_file(args, file) {
+            Ok(id) => println!("{} {} ({})", Glyph::Ok, file, id),
+            Err(error) => {
+                println!("{} {}: {}", Glyph::Failed, file, error);
+                failures += 1;
+            }
+        }
+    }
+
+    println!();
+    println!(
+        "{} of {} file(s) passed",
+        files.len() - failures,
+        files.len()
+    );
+
+    match failures {
+        0 => Ok(()),
+        failures => Err(Error::VerifyFailed(failures)),
+    }
+}
+
+fn verify_file(args: &Args, path: &str) -> Result<String, Error> {
+    verify_archive(path)?;
+
+    let id = manifest_id(path)?;
+
+    if let Some(expected) = args
+        .search
+        .as_deref()
+        .filter(|search| is_extension_id(search))
+    {
+        // Without a version in the expected id any version of the extension passes
+        let matches = match expected.split_once('@') {
+            Some(_) => id.eq_ignore_ascii_case(expected),
+            None => id
+                .split_once('@')
+                .is_some_and(|(name, _)| name.eq_ignore_ascii_case(expected)),
+        };
+
+        if !matches {
+            return Err(Error::ManifestMismatch {
+                expected: expected.to_string(),
+                got: id,
+            });
+        }
+    }
+
+    if let Some(checksum_file) = &args.checksum_file {
+        let mut hasher = Sha256::new();
+        let mut file = File::open(path).map_err(Error::FileRead)?;
+        io::copy(&mut file, &mut hasher).map_err(Error::FileRead)?;
+
+        let filename = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        check_checksum_file(
+            checksum_file,
+            &filename,
+            &format!("{:x}", hasher.finalize()),
+        )?;
+    }
+
+    Ok(id)
+}
+
+// Only the sidecars of files still in the folder are refreshed, and only while the gallery
+// still lists the same version, the saved file would no longer match the metadata otherwise
+async fn refresh_metadata(
+    client: &reqwest::Client,
+    args: &Args,
+    dir: &str,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let mut sidecars: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(Error::FileRead)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .filter(|path| path.with_extension("").is_file())
+        // The state --resume keeps next to a .part download isn't a metadata sidecar
+        .filter(|path| {
+            path.with_extension("")
+                .extension()
+                .is_none_or(|extension| extension != "part")
+        })
+        .collect();
+    sidecars.sort();
+
+    let mut refreshed = 0;
+    let mut failures = 0;
+
+    for sidecar in &sidecars {
+        let name = sidecar.display();
+
+        match refresh_sidecar(client, args, sidecar, deadline).await {
+            Ok(true) => {
+                println!("{} Refreshed {}", Glyph::Ok, name);
+                refreshed += 1;
+            }
+            Ok(false) => println!("Kept {}, the gallery no longer lists its version", name),
+            Err(error) => {
+                println!("{} {}: {}", Glyph::Failed, name, error);
+                failures += 1;
+            }
+        }
+    }
+
+    println!();
+    println!("Refreshed {} of {} sidecar(s)", refreshed, sidecars.len());
+
+    match failures {
+        0 => Ok(()),
+        failures => Err(Error::RefreshMetadata(failures)),
+    }
+}
+
+async fn refresh_sidecar(
+    client: &reqwest::Client,
+    args: &Args,
+    sidecar: &Path,
+    deadline: Option<Instant>,
+) -> Result<bool, Error> {
+    let previous: Value = fs::read_to_string(sidecar)
+        .map_err(Error::FileRead)
+        .and_then(|content| {
+            serde_json::from_str(&content)
+                .map_err(|error| Error::InvalidJson(sidecar.display().to_string(), error))
+        })?;
+    let field = |key: &str| previous[key].as_str().map(str::to_string);
+
+    let id = field("id").ok_or_else(|| Error::UnknownSidecar(sidecar.display().to_string()))?;
+    let version = field("version");
+    let platform = field("platform");
+
+    let extensions = query_extensions(client, args, &id, deadline).await?;
+    let extension = exact_match(extensions, &id)?;
+
+    let index = extension.versions.iter().position(|candidate| {
+        Some(&candidate.version) == version.as_ref()
+            && candidate
+                .targetPlatform
+                .map(|platform| platform.to_string())
+                == platform
+    });
+
+    match index {
+        Some(index) => {
+            write_sidecar(
+                &sidecar.with_extension("").to_string_lossy(),
+                &Metadata::new(&extension, index),
+            )?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// Installs the newest version of every installed extension that has one, without prompting
+async fn auto_update(
+    client: &reqwest::Client,
+    args: &Args,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let installed = list_installed_extensions(&args.program, args.editor_profile.as_deref())?;
+
+    let mut updated = Vec::new();
+    let mut current = Vec::new();
+    let mut missing = Vec::new();
+    let mut failures = Vec::new();
+
+    for (i, (id, version)) in installed.iter().enumerate() {
+        batch_pause(args, i).await;
+        println!("[{}/{}] {} v{}", i + 1, installed.len(), id, version);
+
+        match update_item(client, args, id, version, deadline).await {
+            Ok(Some(latest)) => {
+                if args.dry_run {
+                    println!("v{} is available", latest);
+                }
+                updated.push(format!("{} v{} -> v{}", id, version, latest))
+            }
+            Ok(None) => {
+                println!("Already up to date");
+                current.push(id);
+            }
+            // Extensions installed from a vsix may not be on the marketplace at all
+            Err(Error::Search(_)) => {
+                println!("Not found on the marketplace, skipping it");
+                missing.push(id);
+            }
+            Err(error) => {
+                print_error(&error);
+                failures.push(format!("{}: {}", id, error));
+            }
+        }
+
+        println!();
+    }
+
+    println!(
+        "{} {} of {} extensions, {} already up to date",
+        if args.dry_run { "Outdated" } else { "Updated" },
+        updated.len(),
+        installed.len(),
+        current.len()
+    );
+
+    for line in &updated {
+        println!("\t{} {}", Glyph::Ok, line);
+    }
+
+    if !missing.is_empty() {
+        println!("Not found on the marketplace:");
+        for id in &missing {
+            println!("\t{}", id);
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("Failed:");
+        for line in &failures {
+            println!("\t{} {}", Glyph::Failed, line);
+        }
+        return Err(Error::Batch(failures.len()));
+    }
+
+    Ok(())
+}
+
+// Returns the version that was installed, or None when the installed one is already the newest
+async fn update_item(
+    client: &reqwest::Client,
+    args: &Args,
+    id: &str,
+    version: &str,
+    deadline: Option<Instant>,
+) -> Result<Option<String>, Error> {
+    let extensions = query_extensions(client, args, id, deadline).await?;
+
+    let choice = extensions
+        .iter()
+        .position(|extension| extension.id().eq_ignore_ascii_case(id))
+        .ok_or_else(|| Error::Search(id.to_string()))?;
+
+    let extension = compatible_versions(args, select_extension(extensions, choice + 1)?)?;
+    let index = platform_version_index(&extension, args);
+    let latest = extension.versions[index].version.clone();
+
+    if !is_newer_version(&latest, version) {
+        return Ok(None);
+    }
+
+    if args.dry_run {
+        return Ok(Some(latest));
+    }
+
+    let (tmp_path, _) = download_extension(client, args, &extension, index, deadline).await?;
+
+    // Nothing is saved when updating, the download only lives until the editor has installed it
+    let installed = install_download(args, &extension, index, &tmp_path);
+    fs::remove_file(&tmp_path).map_err(Error::FileDelete)?;
+    installed?;
+
+    Ok(Some(latest))
+}
+
+// Picks the result whose id matches the item exactly, otherwise the first one. An id can pin
+// a version with publisher.name@version. Artifacts already downloaded earlier in the batch
+// are marked as duplicates and skipped
+async fn batch_item(
+    client: &reqwest::Client,
+    args: &Args,
+    item: &str,
+    downloaded: &mut HashSet<String>,
+    result: &mut BatchResult,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let (item, version) = match item.split_once('@') {
+        Some((id, version)) if is_extension_id(id) => (id, Some(version)),
+        _ => (item, None),
+    };
+
+    let extensions = query_extensions(client, args, item, deadline).await?;
+
+    let extension = if args.exact_id(item) {
+        exact_match(extensions, item)?
+    } else {
+        let choice = extensions
+            .iter()
+            .position(|extension| extension.id().eq_ignore_ascii_case(item))
+            .unwrap_or(0);
+
+        select_extension(extensions, choice + 1).map_err(|error| match error {
+            Error::IndexOutOfBound() => Error::Search(item.to_string()),
+            error => error,
+        })?
+    };
+    let extension = compatible_versions(args, extension)?;
+
+    let index = match version {
+        Some(version) => requested_version_index(&extension, version)?,
+        None => platform_version_index(&extension, args),
+    };
+
+    result.id = Some(extension.id());
+    result.version = Some(extension.versions[index].version.clone());
+
+    let key = extension.artifact_key(index);
+    if !downloaded.insert(key.clone()) {
+        println!("Skipping {}, it was already downloaded in this run", key);
+        result.status = BatchStatus::Duplicate;
+        return Ok(());
+    }
+
+    let (tmp_path, filename) =
+        match download_extension(client, args, &extension, index, deadline).await {
+            Err(error @ Error::MissingContribution { .. }) => {
+                println!("Skipping {}", error);
+                result.status = BatchStatus::Filtered;
+                result.error = Some(error.to_string());
+                return Ok(());
+            }
+            other => other?,
+        };
+
+    if args.install {
+        install_download(args, &extension, index, &tmp_path)?;
+
+        result.status = BatchStatus::Installed;
+        return Ok(());
+    }
+
+    let path = save_download(args, &extension, index, tmp_path, &filename)?;
+
+    result.status = BatchStatus::Downloaded;
+    result.bytes = fs::metadata(&path).map(|metadata| metadata.len()).ok();
+    result.path = Some(path);
+
+    Ok(())
+}
+
+fn save_download(
+    args: &Args,
+    extension: &Extension,
+    index: usize,
+    tmp_path: String,
+    filename: &str,
+) -> Result<String, Error> {
+    let path = save_to_output(args, tmp_path, filename)?;
+
+    if args.metadata_sidecar {
+        write_sidecar(&path, &Metadata::new(extension, index))?;
+    }
+
+    Ok(path)
+}
+
+// The sidecar sits next to the saved file as <file>.json
+fn write_sidecar(path: &str, metadata: &Metadata) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(metadata)
+        .map_err(|error| Error::InvalidJson("the metadata".to_string(), error))?;
+
+    fs::write(format!("{}.json", path), json).map_err(Error::FileWrite)
+}
+
+// Moves the downloaded vsix to the output folder, re-packaging it first if asked to
+fn save_to_output(args: &Args, tmp_path: String, filename: &str) -> Result<String, Error> {
+    let (tmp_path, filename) = match args.output_format {
+        OutputFormat::Vsix => (tmp_path, filename.to_string()),
+        OutputFormat::Tar => {
+            let tar_path = repackage_tar_gz(&tmp_path)?;
+            let filename = format!("{}.tar.gz", filename.trim_end_matches(".vsix"));
+            (tar_path, filename)
+        }
+    };
+
+    let path = format!("{}/{}", &args.output, &filename);
+
+    let path = match args.on_collision {
+        _ if !Path::new(&path).exists() => path,
+        CollisionPolicy::Overwrite => path,
+        CollisionPolicy::Renumber => renumbered_path(&args.output, &filename),
+        CollisionPolicy::Skip => {
+            println!("Skipping {}, the file already exists", path);
+            fs::remove_file(&tmp_path).map_err(Error::FileDelete)?;
+            if !args.json {
+                print_saved_path(&path);
+            }
+            return Ok(path);
+        }
+    };
+
+    move_to(tmp_path, path.clone())?;
+    if !args.json {
+        print_saved_path(&path);
+    }
+
+    Ok(path)
+}
+
+// foo.vsix becomes foo-1.vsix, then foo-2.vsix and so on until a free name is found
+fn renumbered_path(dir: &str, filename: &str) -> String {
+    let (stem, extension) = [".tar.gz", ".vsix"]
+        .iter()
+        .find_map(|extension| {
+            filename
+                .strip_suffix(extension)
+                .map(|stem| (stem, *extension))
+        })
+        .unwrap_or((filename, ""));
+
+    (1..)
+        .map(|i| format!("{}/{}-{}{}", dir, stem, i, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap_or_default()
+}
+
+// Prints what would be downloaded as json, the size comes from a HEAD request and is null when it can't be known
+async fn resolve(
+    client: &reqwest::Client,
+    args: &Args,
+    extension: &Extension,
+    index: usize,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let version = &extension.versions[index];
+    let urls = version.vsix_urls();
+
+    let (url, size) = match send_asset(client, Method::HEAD, &urls, 0, None, deadline, 1).await {
+        // content_length() is the size of the empty HEAD body, the header has the real one
+        Ok(resp) => (
+            resp.url().to_string(),
+            PartialMeta::from_headers(resp.headers()).total,
+        ),
+        Err(Error::Deadline()) => return Err(Error::Deadline()),
+        // A version without any vsix has no url to fall back to
+        Err(_) => (urls.first().cloned().ok_or(Error::NoAssets())?, None),
+    };
+
+    let resolution = Resolution {
+        publisher: &extension.publisher.publisherName,
+        name: &extension.extensionName,
+        version: &version.version,
+        platform: version
+            .targetPlatform
+            .as_ref()
+            .map(|platform| platform.to_string()),
+        url,
+        size,
+    };
+
+    let json = serde_json::to_string_pretty(&resolution)
+        .map_err(|error| Error::InvalidJson("the resolution".to_string(), error))?;
+    print_output(&json);
+
+    if args.clipboard {
+        copy_to_clipboard(&resolution.url)?;
+    }
+
+    Ok(())
+}
+
+async fn download_since(
+    client: &reqwest::Client,
+    args: &Args,
+    extension: &Extension,
+    since: &str,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let indexes = versions_since(extension, args, since);
+
+    println!(
+        "Found {} version(s) of {} newer than {}",
+        indexes.len(),
+        extension.id(),
+        since
+    );
+
+    for (i, index) in indexes.iter().enumerate() {
+        batch_pause(args, i).await;
+        println!();
+        println!(
+            "[{}/{}] v{}",
+            i + 1,
+            indexes.len(),
+            extension.versions[*index].version
+        );
+
+        let (tmp_path, filename) =
+            download_extension(client, args, extension, *index, deadline).await?;
+        save_download(args, extension, *index, tmp_path, &filename)?;
+    }
+
+    println!();
+    println!("Downloaded {} new version(s)", indexes.len());
+
+    Ok(())
+}
+
+// One index per version newer than `since`, the build for this platform is preferred to the universal one
+// and builds for other platforms are left out
+fn versions_since(extension: &Extension, args: &Args, since: &str) -> Vec<usize> {
+    let target_platform = get_target_platform();
+    let mut indexes: Vec<usize> = Vec::new();
+
+    for (i, version) in extension.versions.iter().enumerate() {
+        if (version.is_pre_release() && !args.pre_release)
+            || !is_newer_version(&version.version, since)
+        {
+            continue;
+        }
+
+        match version.targetPlatform {
+            None | Some(TargetPlatform::Universal) => {}
+            Some(platform) if platform == target_platform => {}
+            Some(_) => continue,
+        }
+
+        match indexes
+            .iter()
+            .position(|index| extension.versions[*index].version == version.version)
+        {
+            Some(existing) if version.targetPlatform == Some(target_platform) => {
+                indexes[existing] = i
+            }
+            Some(_) => {}
+            None => indexes.push(i),
+        }
+    }
+
+    indexes
+}
+
+// Prefers the newest stable version built for the current platform, otherwise the newest stable one.
+// The marketplace can list a pre-release first, those are only picked with --pre-release
+fn platform_version_index(extension: &Extension, args: &Args) -> usize {
+    select_version(extension, args.pre_release, get_target_platform())
+}
+
+// Drops the versions the editor of --engine can't install, which the marketplace still lists
+fn compatible_versions(args: &Args, mut extension: Extension) -> Result<Extension, Error> {
+    let Some(engine) = &args.engine else {
+        return Ok(extension);
+    };
+
+    let required = extension
+        .versions
+        .first()
+        .and_then(|version| version.engine())
+        .unwrap_or("*")
+        .to_string();
+
+    extension.versions.retain(|version| {
+        version
+            .engine()
+            .is_none_or(|required| engine_compatible(required, engine))
+    });
+
+    if !extension.is_available() {
+        return Err(Error::Incompatible {
+            id: extension.id(),
+            engine: engine.clone(),
+            required,
+        });
+    }
+
+    Ok(extension)
+}
+
+fn requested_version_index(extension: &Extension, version: &str) -> Result<usize, Error> {
+    find_version(extension, version, get_target_platform()).ok_or_else(|| Error::VersionNotFound {
+        id: extension.id(),
+        version: version.to_string(),
+        nearby: nearby_versions(extension, version).join(", "),
+    })
+}
+
+// The three versions right above and the three right below the one that was asked for
+fn nearby_versions(extension: &Extension, version: &str) -> Vec<String> {
+    let mut versions: Vec<&str> = Vec::new();
+    for candidate in &extension.versions {
+        if !versions.contains(&candidate.version.as_str()) {
+            versions.push(&candidate.version);
+        }
+    }
+
+    versions.sort_by(|a, b| {
+        if is_newer_version(a, b) {
+            std::cmp::Ordering::Less
+        } else if is_newer_version(b, a) {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    });
+
+    let split = versions
+        .iter()
+        .position(|candidate| !is_newer_version(candidate, version))
+        .unwrap_or(versions.len());
+
+    versions[split.saturating_sub(3)..(split + 3).min(versions.len())]
+        .iter()
+        .map(|candidate| candidate.to_string())
+        .collect()
+}
+
+fn print_info(args: &Args, extension: &Extension, index: usize) -> Result<(), Error> {
+    if !args.json {
+        print_metadata(extension, index);
+        return Ok(());
+    }
+
+    let metadata = Metadata::new(extension, index);
+
+    let json = serde_json::to_string_pretty(&metadata)
+        .map_err(|error| Error::InvalidJson("the metadata".to_string(), error))?;
+    print_output(&json);
+
+    Ok(())
+}
+
+// The version each result would download, in the order of the search
+fn print_search_json(args: &Args, extensions: &[Extension]) -> Result<(), Error> {
+    let metadata: Vec<Metadata> = extensions
+        .iter()
+        .map(|extension| Metadata::new(extension, platform_version_index(extension, args)))
+        .collect();
+
+    let json = serde_json::to_string_pretty(&metadata)
+        .map_err(|error| Error::InvalidJson("the results".to_string(), error))?;
+    print_output(&json);
+
+    Ok(())
+}
+
+fn print_download_json(extension: &Extension, index: usize, path: &str) -> Result<(), Error> {
+    let output = DownloadOutput {
+        metadata: Metadata::new(extension, index),
+        path,
+    };
+
+    let json = serde_json::to_string_pretty(&output)
+        .map_err(|error| Error::InvalidJson("the download".to_string(), error))?;
+    print_output(&json);
+
+    Ok(())
+}
+
+fn pre_release_tag(version: &Versions) -> &'static str {
+    if version.is_pre_release() {
+        " (pre-release)"
+    } else {
+        ""
+    }
+}
+
+fn print_versions(extension: &Extension) {
+    println!("{} versions:", extension.id());
+
+    for version in &extension.versions {
+        println!(
+            "\t{} {} {}{}",
+            version.version,
+            version.targetPlatform.unwrap_or(TargetPlatform::Universal),
+            version.lastUpdated,
+            pre_release_tag(version)
+        );
+    }
+}
+
+fn print_metadata(extension: &Extension, index: usize) {
+    let description = match &extension.shortDescription {
+        Some(desc) => desc,
+        _ => "",
+    };
+
+    println!("{}:", &extension.extensionName);
+    println!("{}", description);
+    println!();
+    println!("\tPublisher: {}", &extension.publisher.publisherName);
+    println!(
+        "\tVersion: {}{}",
+        &extension.versions[index].version,
+        pre_release_tag(&extension.versions[index])
+    );
+    println!("\tFlags: {}", &extension.flags);
+    println!("\tLast updated: {}", &extension.lastUpdated);
+    println!("\tPublished date: {}", &extension.publishedDate);
+    println!("\tRelease date: {}", &extension.releaseDate);
+    println!();
+}
+
+fn output_filename(args: &Args, extension: &Extension, index: usize) -> String {
+    let mut publisher_name = extension.publisher.publisherName.clone();
+    let mut extension_name = extension.extensionName.clone();
+    let mut version = extension.versions[index].version.clone();
+
+    if args.lowercase_names {
+        publisher_name = publisher_name.to_lowercase();
+        extension_name = extension_name.to_lowercase();
+    }
+
+    if args.normalize_version {
+        version = normalize_version(&version);
+    }
+
+    // The editor names the builds of a platform publisher.name-version@platform.vsix too
+    match extension.versions[index].targetPlatform {
+        Some(platform) if args.all_platforms && platform != TargetPlatform::Universal => format!(
+            "{}.{}-{}@{}.vsix",
+            publisher_name, extension_name, version, platform
+        ),
+        _ => format!("{}.{}-{}.vsix", publisher_name, extension_name, version),
+    }
+}
+
+// Checks the download matches the sha256 the registry publishes, if any, and is a whole vsix.
+// Checked before anything else reads the file, so a broken download is never installed
+async fn verify_download(
+    client: &reqwest::Client,
+    version: &Versions,
+    path: &str,
+    filename: &str,
+    sha256: &str,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    if let Some(url) = version.sha256_url() {
+        let resp = send_asset(
+            client,
+            Method::GET,
+            &[url.to_string()],
+            0,
+            None,
+            deadline,
+            1,
+        )
+        .await?;
+        let body = resp.text().await.map_err(Error::ReqwestDns)?;
+        let expected = body.split_whitespace().next().unwrap_or_default();
+
+        if !expected.eq_ignore_ascii_case(sha256) {
+            return Err(Error::CorruptDownload(
+                filename.to_string(),
+                format!(
+                    "its sha256 is {} but the registry published {}",
+                    sha256, expected
+                ),
+            ));
+        }
+    }
+
+    verify_vsix(path)
+        .map_err(|error| Error::CorruptDownload(filename.to_string(), error.to_string()))
+}
+
+fn manage_cache(args: &Args, action: &CacheAction) -> Result<(), Error> {
+    let dir = cache::cache_dir()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_default();
+
+    match action {
+        CacheAction::List => {
+            let entries = cache::list()?;
+
+            if args.json {
+                let json = serde_json::to_string_pretty(&entries)
+                    .map_err(|error| Error::InvalidJson("the cache".to_string(), error))?;
+                print_output(&json);
+                return Ok(());
+            }
+
+            for entry in &entries {
+                println!(
+                    "{}@{} ({}) {}, {} day(s) old",
+                    entry.id,
+                    entry.version,
+                    entry.platform,
+                    format_size(entry.size as usize),
+                    entry.age().as_secs() / 86400
+                );
+            }
+
+            let size: u64 = entries.iter().map(|entry| entry.size).sum();
+            println!(
+                "{} file(s), {} in {}",
+                entries.len(),
+                format_size(size as usize),
+                dir
+            );
+        }
+        CacheAction::Clean {
+            older_than,
+            max_size,
+        } => {
+            let removed = cache::clean(
+                older_than.map(|days| Duration::from_secs(days * 86400)),
+                max_size.map(|megabytes| megabytes * 1000 * 1000),
+            )?;
+
+            let size: u64 = removed.iter().map(|entry| entry.size).sum();
+            println!(
+                "{} Removed {} file(s), {} from {}",
+                Glyph::Ok,
+                removed.len(),
+                format_size(size as usize),
+                dir
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Streams the vsix into the temp dir and returns its path along with the file name
+async fn download_extension(
+    client: &reqwest::Client,
+    args: &Args,
+    extension: &Extension,
+    index: usize,
+    deadline: Option<Instant>,
+) -> Result<(String, String), Error> {
+    let filename = output_filename(args, extension, index);
+
+    let download_urls = extension.versions[index].vsix_urls();
+
+    let cached = if args.no_cache {
+        None
+    } else {
+        let tmp_path = unique_tmp_path(&filename);
+        cache::fetch(extension, index, &tmp_path)?.map(|sha256| (tmp_path, sha256))
+    };
+    let from_cache = cached.is_some();
+
+    // Resumable downloads need a path that stays the same between runs
+    let part_dir = args.resume.then(env::temp_dir);
+    let (tmp_path, sha256) = match cached {
+        Some(cached) => {
+            println!("{} Using the cached download of {}", Glyph::Ok, filename);
+            cached
+        }
+        None => {
+            download_file(
+                client,
+                args,
+                &download_urls,
+                &filename,
+                part_dir.as_deref(),
+                deadline,
+            )
+            .await?
+        }
+    };
+
+    if let Err(error) = verify_download(
+        client,
+        &extension.versions[index],
+        &tmp_path,
+        &filename,
+        &sha256,
+        deadline,
+    )
+    .await
+    {
+        fs::remove_file(&tmp_path).map_err(Error::FileDelete)?;
+        return Err(error);
+    }
+
+    if let Some(checksum_file) = &args.checksum_file {
+        if let Err(error) = check_checksum_file(checksum_file, &filename, &sha256) {
+            fs::remove_file(&tmp_path).map_err(Error::FileDelete)?;
+            return Err(error);
+        }
+    }
+
+    if let Some(lockfile) = &args.lockfile {
+        if let Err(error) = check_lockfile(lockfile, &extension.artifact_key(index), &sha256) {
+            fs::remove_file(&tmp_path).map_err(Error::FileDelete)?;
+            return Err(error);
+        }
+    }
+
+    if args.validate_manifest_id {
+        let expected = format!("{}@{}", extension.id(), extension.versions[index].version);
+
+        match manifest_id(&tmp_path) {
+            Ok(got) if got.eq_ignore_ascii_case(&expected) => {}
+            result => {
+                fs::remove_file(&tmp_path).map_err(Error::FileDelete)?;
+                return Err(Error::ManifestMismatch {
+                    expected,
+                    got: result?,
+                });
+            }
+        }
+    }
+
+    if !args.requires.is_empty() {
+        let missing = missing_contributions(&tmp_path, &args.requires)?;
+
+        if !missing.is_empty() {
+            fs::remove_file(&tmp_path).map_err(Error::FileDelete)?;
+            return Err(Error::MissingContribution {
+                id: extension.id(),
+                missing: missing.join(", "),
+            });
+        }
+    }
+
+    if let Some(dir) = &args.download_icon {
+        download_icon(client, dir, extension, index, deadline).await?;
+    }
+
+    if let Some(format) = args.manifest {
+        println!();
+        print_manifest(&tmp_path, format == ManifestFormat::Raw)?;
+    }
+
+    if args.theme_preview {
+        println!();
+        preview_themes(&tmp_path)?;
+    }
+
+    // Only what passed every check is cached. Not being able to cache the download doesn't stop
+    // it from being saved
+    if !(from_cache || args.no_cache) {
+        if let Err(error) = cache::store(extension, index, &tmp_path) {
+            eprintln!("Couldn't cache {}: {}", filename, error);
+        }
+    }
+
+    Ok((tmp_path, filename))
+}
+
+// Downloads the file into the temporary folder and returns where it is with its sha256.
+// With a part folder the download goes to a .part file there that later runs can resume
+async fn download_file(
+    client: &reqwest::Client,
+    args: &Args,
+    download_urls: &[String],
+    filename: &str,
+    part_dir: Option<&Path>,
+    deadline: Option<Instant>,
+) -> Result<(String, String), Error> {
+    if args.connections > 1 && part_dir.is_none() {
+        if let Some(downloaded) =
+            download_parallel(client, args, download_urls, filename, deadline).await?
+        {
+            return Ok(downloaded);
+        }
+    }
+
+    let (download_path, offset) = if let Some(part_dir) = part_dir {
+        let part_path = format!("{}/{}.part", part_dir.display(), filename);
+        let offset = prepare_resume(client, download_urls, &part_path, deadline).await?;
+        (part_path, offset)
+    } else {
+        (unique_tmp_path(filename), 0)
+    };
+
+    let resp = download_asset(client, download_urls, offset, deadline, 1).await?;
+
+    // A server that ignores the range answers with the whole file
+    let offset = if resp.status() == StatusCode::PARTIAL_CONTENT {
+        offset
+    } else {
+        0
+    };
+
+    let total_size = offset + resp.content_length().ok_or(Error::ReqwestLength())?;
+
+    let total_size_format = format_size(total_size as usize);
+
+    if offset > 0 {
+        println!(
+            "{} Resuming the download of {} from {}...",
+            Glyph::Download,
+            total_size_format,
+            format_size(offset as usize)
+        );
+    } else {
+        println!("{} Downloading {}...", Glyph::Download, total_size_format);
+    }
+
+    if !args.no_preflight_disk_space {
+        check_disk_space(&env::temp_dir(), total_size - offset)?;
+    }
+
+    let mut hasher = Sha256::new();
+
+    let file = if offset > 0 {
+        let mut partial = File::open(&download_path).map_err(Error::FileRead)?;
+        io::copy(&mut partial, &mut hasher).map_err(Error::FileRead)?;
+
+        OpenOptions::new()
+            .append(true)
+            .open(&download_path)
+            .map_err(Error::FileWrite)?
+    } else {
+        File::create(&download_path).map_err(Error::FileWrite)?
+    };
+    // Chunks can be a few bytes each on fast connections, buffering avoids a write call for every one
+    let mut file = BufWriter::with_capacity(WRITE_BUFFER_SIZE, file);
+    let mut request = request_id(&resp);
+    let mut request_start = Instant::now();
+    let mut request_offset = offset as usize;
+    let mut stream = resp.bytes_stream();
+
+    let mut progress = offset as usize;
+    let mut bar = ProgressBar::new(
+        total_size,
+        offset,
+        args.progress_bar_style,
+        args.bar_colors(),
+        args.quiet,
+    );
+    let mut last_redraw: Option<Instant> = None;
+    let mut stalls = 0;
+    let mut backoff = Backoff::new(args.retry_jitter);
+    let mut stalled = None;
+    loop {
+        let next = with_deadline(deadline, async {
+            Ok(match args.stall_timeout {
+                Some(seconds) => tokio::time::timeout(Duration::from_secs(seconds), stream.next())
+                    .await
+                    .map_err(|_| seconds),
+                None => Ok(stream.next().await),
+            })
+        })
+        .await?;
+
+        let byte = match next {
+            Ok(Some(byte)) => byte,
+            Ok(None) => break,
+            Err(seconds) => {
+                if stalls >= args.stall_retries {
+                    stalled = Some(Error::Stalled(seconds));
+                    break;
+                }
+                stalls += 1;
+
+                eprintln!(
+                    "\nNo data for {} seconds, continuing from {} ({}/{})",
+                    seconds,
+                    format_size(progress),
+                    stalls,
+                    args.stall_retries
+                );
+
+                let delay = backoff.next_delay();
+                with_deadline(deadline, async {
+                    tokio::time::sleep(delay).await;
+                    Ok(())
+                })
+                .await?;
+
+                // Only a server that honors the range can continue, restarting would desync the hash
+                // The stalled request ends here, its bytes are recorded before the next one starts
+                if let Some(request) = request {
+                    record_transfer(
+                        request,
+                        (progress - request_offset) as u64,
+                        request_start.elapsed(),
+                    );
+                }
+
+                let resp =
+                    download_asset(client, download_urls, progress as u64, deadline, stalls + 1)
+                        .await?;
+                if resp.status() != StatusCode::PARTIAL_CONTENT {
+                    request = None;
+                    stalled = Some(Error::Stalled(seconds));
+                    break;
+                }
+
+                request = request_id(&resp);
+                request_start = Instant::now();
+                request_offset = progress;
+                stream = resp.bytes_stream();
+                continue;
+            }
+        };
+
+        let Some(chunk) = body_chunk(byte, progress as u64, total_size)? else {
+            break;
+        };
+        progress += chunk.len();
+        hasher.update(&chunk);
+        file.write_all(&chunk).map_err(Error::FileWrite)?;
+
+        // Redraws are throttled, the last chunk is always drawn so the bar ends at 100%
+        let finished = progress as u64 == total_size;
+        if !finished
+            && last_redraw.is_some_and(|redraw| redraw.elapsed() < PROGRESS_REDRAW_INTERVAL)
+        {
+            continue;
+        }
+        last_redraw = Some(Instant::now());
+
+        if args.progress_json {
+            ProgressEvent::Progress {
+                bytes: progress,
+                total: total_size,
+            }
+            .emit();
+        } else {
+            bar.update(progress as u64)?;
+        }
+    }
+
+    file.flush().map_err(Error::FileWrite)?;
+
+    if let Some(request) = request {
+        record_transfer(
+            request,
+            (progress - request_offset) as u64,
+            request_start.elapsed(),
+        );
+    }
+
+    if let Err(error) = check_length(total_size, progress as u64) {
+        drop(file);
+
+        // A shorter partial download can still be resumed on the next run
+        if !(part_dir.is_some() && (progress as u64) < total_size) {
+            fs::remove_file(&download_path).map_err(Error::FileDelete)?;
+        }
+
+        return Err(stalled.unwrap_or(error));
+    }
+
+    let tmp_path = if part_dir.is_some() {
+        let tmp_path = unique_tmp_path(filename);
+        fs::rename(&download_path, &tmp_path).map_err(Error::FileWrite)?;
+        let _ = fs::remove_file(format!("{}.json", download_path));
+        tmp_path
+    } else {
+        download_path
+    };
+
+    if !args.progress_json {
+        bar.finish()?;
+    }
+    println!("{} Download successful.", Glyph::Ok);
+
+    let sha256 = format!("{:x}", hasher.finalize());
+
+    Ok((tmp_path, sha256))
+}
+
+// Splits the file in one range per connection and fetches them at the same time. Gives back
+// None when the server can't serve ranges, so the caller can fall back to a single stream
+async fn download_parallel(
+    client: &reqwest::Client,
+    args: &Args,
+    download_urls: &[String],
+    filename: &str,
+    deadline: Option<Instant>,
+) -> Result<Option<(String, String)>, Error> {
+    let head = match send_asset(client, Method::HEAD, download_urls, 0, None, deadline, 1).await {
+        Ok(resp) => resp,
+        Err(Error::Deadline()) => return Err(Error::Deadline()),
+        Err(_) => return Ok(None),
+    };
+
+    let ranges_supported = head
+        .headers()
+        .get(ACCEPT_RANGES)
+        .is_some_and(|value| value == "bytes");
+    let total_size = match PartialMeta::from_headers(head.headers()).total {
+        Some(total) if ranges_supported && total >= args.connections => total,
+        _ => {
+            println!("The server doesn't support ranges, downloading with a single connection");
+            return Ok(None);
+        }
+    };
+
+    println!(
+        "{} Downloading {} with {} connections...",
+        Glyph::Download,
+        format_size(total_size as usize),
+        args.connections
+    );
+
+    if !args.no_preflight_disk_space {
+        check_disk_space(&env::temp_dir(), total_size)?;
+    }
+
+    let chunk_size = total_size.div_ceil(args.connections);
+    let ranges: Vec<(u64, u64)> = (0..args.connections)
+        .map(|i| (i * chunk_size, ((i + 1) * chunk_size).min(total_size) - 1))
+        .filter(|(start, end)| start <= end)
+        .collect();
+
+    let progress = AtomicU64::new(0);
+
+    let fetch = futures::future::try_join_all(ranges.iter().map(|&(first, last)| {
+        let progress = &progress;
+
+        async move {
+            let resp = send_asset(
+                client,
+                Method::GET,
+                download_urls,
+                first,
+                Some(last),
+                deadline,
+                1,
+            )
+            .await?;
+
+            if resp.status() != StatusCode::PARTIAL_CONTENT {
+                return Ok(None);
+            }
+
+            let request = request_id(&resp);
+            let part_start = Instant::now();
+            let mut part = Vec::with_capacity((last - first + 1) as usize);
+            let mut stream = resp.bytes_stream();
+
+            while let Some(chunk) =
+                with_deadline(deadline, async { Ok(stream.next().await) }).await?
+            {
+                let Some(chunk) = body_chunk(chunk, part.len() as u64, last - first + 1)? else {
+                    break;
+                };
+                progress.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                part.extend_from_slice(&chunk);
+            }
+
+            if let Some(request) = request {
+                record_transfer(request, part.len() as u64, part_start.elapsed());
+            }
+
+            check_length(last - first + 1, part.len() as u64)?;
+
+            Ok(Some(part))
+        }
+    }));
+    tokio::pin!(fetch);
+
+    let mut redraw = tokio::time::interval(PROGRESS_REDRAW_INTERVAL);
+    let mut bar = ProgressBar::new(
+        total_size,
+        0,
+        args.progress_bar_style,
+        args.bar_colors(),
+        args.quiet,
+    );
+
+    let parts = loop {
+        tokio::select! {
+            parts = &mut fetch => break parts?,
+            _ = redraw.tick() => print_parallel_progress(args, &mut bar, progress.load(Ordering::Relaxed), total_size)?,
+        }
+    };
+
+    // A server can advertise ranges and still answer with the whole file
+    let parts: Vec<Vec<u8>> = match parts.into_iter().collect() {
+        Some(parts) => parts,
+        None => {
+            println!();
+            println!("The server ignored the ranges, downloading with a single connection");
+            return Ok(None);
+        }
+    };
+
+    print_parallel_progress(args, &mut bar, total_size, total_size)?;
+    if !args.progress_json {
+        bar.finish()?;
+    }
+
+    let tmp_path = unique_tmp_path(filename);
+    let mut file = File::create(&tmp_path).map_err(Error::FileWrite)?;
+    let mut hasher = Sha256::new();
+
+    for part in &parts {
+        hasher.update(part);
+        file.write_all(part).map_err(Error::FileWrite)?;
+    }
+
+    println!("{} Download successful.", Glyph::Ok);
+
+    Ok(Some((tmp_path, format!("{:x}", hasher.finalize()))))
+}
+
+fn print_parallel_progress(
+    args: &Args,
+    bar: &mut ProgressBar,
+    progress: u64,
+    total_size: u64,
+) -> Result<(), Error> {
+    if args.progress_json {
+        ProgressEvent::Progress {
+            bytes: progress as usize,
+            total: total_size,
+        }
+        .emit();
+        return Ok(());
+    }
+
+    bar.update(progress)
+}
+
+fn build_client(args: &Args) -> Result<reqwest::Client, Error> {
+    // The marketplace may compress its answers, these send Accept-Encoding and decode the body transparently
+    let mut builder = reqwest::Client::builder()
+        .gzip(true)
+        .deflate(true)
+        .brotli(true);
+
+    // Binding to the unspecified address of a family forces every connection to use it
+    if args.prefer_ipv4 {
+        builder = builder.local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    } else if args.prefer_ipv6 {
+        builder = builder.local_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+    }
+
+    if args.verbose {
+        builder = builder.dns_resolver(Arc::new(TimedResolver));
+    }
+
+    if let Some(proxy) = &args.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy).map_err(Error::ClientBuild)?);
+    }
+
+    builder.build().map_err(Error::ClientBuild)
+}
+
+// Sends the smallest possible query to check the connection, the proxy and the token
+async fn probe(
+    client: &reqwest::Client,
+    args: &Args,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    println!("Probing {}", args.api_url());
+
+    let options = RequestOptions {
+        filters: vec![RequestFilters {
+            pageNumber: 1,
+            pageSize: 1,
+            criteria: if args.no_target_filter {
+                Vec::new()
+            } else {
+                vec![RequestCriteria {
+                    filterType: FilterType::Target as i8,
+                    value: args.api_preset.target().to_string(),
+                }]
+            },
+        }],
+        flags: query_flags(),
+    };
+
+    let (url, request) = match args.registry() {
+        Registry::Marketplace => {
+            let url = format!("{}?api-version={}", args.api_url(), &args.api_version);
+            let request = client
+                .post(&url)
+                .header(CONTENT_TYPE, "application/json")
+                .json(&options);
+            (url, request)
+        }
+        Registry::Openvsx => {
+            let url = format!("{}/-/search?size=1", openvsx::api_root(args.api_url()));
+            (url.clone(), client.get(url))
+        }
+    };
+
+    let token = args.token.as_deref().map(bearer).transpose()?;
+    let request = authorize(request, &url, args.api_url(), token.as_ref());
+
+    let start = Instant::now();
+    let resp = with_deadline(deadline, async {
+        request.send().await.map_err(Error::ReqwestDns)
+    })
+    .await?;
+    let latency = start.elapsed();
+
+    let status = resp.status();
+
+    println!();
+    println!("\tStatus: {}", status);
+    println!("\tLatency: {} ms", latency.as_millis());
+    println!("\tProxy: {}", args.proxy.as_deref().unwrap_or("none"));
+    println!(
+        "\tToken: {}",
+        if args.token.is_some() {
+            "sent"
+        } else {
+            "not sent"
+        }
+    );
+
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(Error::Probe(format!(
+            "the marketplace refused the credentials ({})",
+            status
+        ))),
+        status if !status.is_success() => Err(Error::Probe(status.to_string())),
+        _ => Ok(()),
+    }
+}
+
+async fn query_extensions(
+    client: &reqwest::Client,
+    args: &Args,
+    search: &str,
+    deadline: Option<Instant>,
+) -> Result<Vec<Extension>, Error> {
+    with_deadline(deadline, async {
+        let mut page_size = args.limit;
+        let mut backoff = Backoff::new(args.retry_jitter);
+        let mut attempt = 1;
+
+        loop {
+            let query = query_with_fallback(client, args, search, page_size, attempt);
+
+            let timeout = match args.search_timeout {
+                Some(seconds) if page_size > 1 => Duration::from_secs(seconds),
+                _ => return query.await,
+            };
+
+            match tokio::time::timeout(timeout, query).await {
+                Ok(result) => return result,
+                Err(_) => {
+                    page_size = (page_size / 2).max(1);
+                    attempt += 1;
+                    eprintln!(
+                        "The search took longer than {} seconds, retrying with a limit of {}",
+                        timeout.as_secs(),
+                        page_size
+                    );
+                    tokio::time::sleep(backoff.next_delay()).await;
+                }
+            }
+        }
+    })
+    .await
+}
+
+// Preview api versions get retired by the marketplace, the fallbacks keep the search working when that happens.
+// Each fallback is one more attempt at the same query
+async fn query_with_fallback(
+    client: &reqwest::Client,
+    args: &Args,
+    search: &str,
+    page_size: i16,
+    attempt: u32,
+) -> Result<Vec<Extension>, Error> {
+    let fallbacks = if args.prefer_stable_api {
+        &args.api_fallback[..]
+    } else {
+        &[]
+    };
+
+    let mut result = query_page(client, args, search, page_size, &args.api_version, attempt).await;
+
+    for (index, version) in fallbacks.iter().enumerate() {
+        match result {
+            Err(Error::ApiVersion(rejected, _)) => {
+                eprintln!(
+                    "The marketplace doesn't support the api version {}, retrying with {}",
+                    rejected, version
+                );
+                result = query_page(
+                    client,
+                    args,
+                    search,
+                    page_size,
+                    version,
+                    attempt + index as u32 + 1,
+                )
+                .await;
+            }
+            result => return result,
+        }
+    }
+
+    result
+}
+
+async fn query_page(
+    client: &reqwest::Client,
+    args: &Args,
+    search: &str,
+    page_size: i16,
+    api_version: &str,
+    attempt: u32,
+) -> Result<Vec<Extension>, Error> {
+    let options = RequestOptions {
+        filters: vec![RequestFilters {
+            pageNumber: 1,
+            pageSize: page_size,
+            criteria: query_criteria(args, search),
+        }],
+        flags: query_flags(),
+    };
+
+    if args.dump_request {
+        let body = serde_json::to_string_pretty(&options)
+            .map_err(|error| Error::InvalidJson("the request".to_string(), error))?;
+        eprintln!("{}", body);
+    }
+
+    // A response saved with --save-query-response can be replayed with a file:// url
+    let gallery = GalleryClient::builder()
+        .http_client(client.clone())
+        .api_url(args.api_url())
+        .api_version(api_version)
+        .registry(args.registry())
+        .attempt(attempt);
+    let gallery = match &args.token {
+        Some(token) => gallery.token(token),
+        None => gallery,
+    }
+    .build()?;
+    let body = gallery.query_raw(&options).await?;
+
+    // Written before parsing so a response that fails to parse can still be looked at
+    if let Some(path) = &args.save_query_response {
+        fs::write(path, &body).map_err(Error::FileWrite)?;
+    }
+
+    parse_response(&body, args.strict_json)
+}
+
+fn query_criteria(args: &Args, search: &str) -> Vec<RequestCriteria> {
+    let fields = if args.list_publishers {
+        &[SearchField::Publisher, SearchField::Publisherdisplayname][..]
+    } else {
+        &args.search_fields[..]
+    };
+
+    // The gallery matches the whole publisher.name against the name criterion, like the editor does
+    let mut criteria: Vec<RequestCriteria> = if args.exact_id(search) {
+        vec![RequestCriteria {
+            filterType: FilterType::Name as i8,
+            value: search.trim().to_string(),
+        }]
+    } else {
+        fields
+            .iter()
+            .map(|field| RequestCriteria {
+                filterType: field.filter_type() as i8,
+                value: search.to_string(),
+            })
+            .collect()
+    };
+
+    if !args.no_target_filter {
+        criteria.push(RequestCriteria {
+            filterType: FilterType::Target as i8,
+            value: args.api_preset.target().to_string(),
+        });
+    }
+
+    // The gallery compares the version itself, a range is written like [1.80.0,1.90.0)
+    if let Some(version) = &args.installation_target_version {
+        let filter_type = if version.contains([',', '[', '(']) {
+            FilterType::InstallationTargetVersionRange
+        } else {
+            FilterType::InstallationTargetVersion
+        };
+
+        criteria.push(RequestCriteria {
+            filterType: filter_type as i8,
+            value: version.clone(),
+        });
+    }
+
+    let exclude_flags = args
+        .exclude_flags
+        .iter()
+        .fold(RequestFlags::Unpublished as i32, |flags, flag| {
+            flags | *flag as i32
+        });
+
+    criteria.push(RequestCriteria {
+        filterType: FilterType::ExcludeWithFlags as i8,
+        value: exclude_flags.to_string(),
+    });
+
+    criteria
+}
+
+// Position of the extension picked the last time the same search was made
+fn default_index(extensions: &[Extension], remembered: Option<&str>) -> Option<usize> {
+    let remembered = remembered?;
+    extensions
+        .iter()
+        .position(|extension| extension.id().eq_ignore_ascii_case(remembered))
+}
+
+fn index_prompt(default: Option<usize>) -> String {
+    match default {
+        Some(default) => format!(
+            "Input the index of the extension you want to download [default: {}]: ",
+            default + 1
+        ),
+        None => "Input the index of the extension you want to download: ".to_owned(),
+    }
+}
+
+// Shows the version that would be picked, which is only a pre-release with --pre-release
+fn print_extensions(extensions: &[Extension], default: Option<usize>, args: &Args) {
+    if args.banners() {
+        println!("Found {} extensions", extensions.len());
+        println!();
+    }
+
+    for (i, extension) in extensions.iter().enumerate() {
+        println!(
+            "[{}] : {}{}",
+            i + 1,
+            extension_label(extension, args),
+            if default == Some(i) { " [default]" } else { "" }
+        );
+    }
+
+    println!();
+}
+
+fn extension_label(extension: &Extension, args: &Args) -> String {
+    let version = match extension
+        .versions
+        .get(platform_version_index(extension, args))
+    {
+        Some(version) if extension.is_available() => {
+            format!("v{}{}", version.version, pre_release_tag(version))
+        }
+        _ => "(not available)".to_string(),
+    };
+
+    format!(
+        "{} by {} {}",
+        extension.extensionName, extension.publisher.publisherName, version
+    )
+}
+
+fn list_publishers(extensions: &[Extension], search: &str, banner: bool) -> Result<(), Error> {
+    let mut seen = HashSet::new();
+    let publishers: Vec<_> = extensions
+        .iter()
+        .map(|extension| &extension.publisher)
+        .filter(|publisher| seen.insert(&publisher.publisherId))
+        .collect();
+
+    if publishers.is_empty() {
+        return Err(Error::Search(search.to_string()));
+    }
+
+    if banner {
+        println!("Found {} publishers", publishers.len());
+        println!();
+    }
+
+    for (i, publisher) in publishers.iter().enumerate() {
+        println!(
+            "[{}] : {} ({}){}",
+            i + 1,
+            publisher.publisherName,
+            publisher.displayName,
+            if publisher.is_verified() {
+                " verified"
+            } else {
+                ""
+            }
+        );
+    }
+
+    Ok(())
+}
+
+fn select_extension(mut extensions: Vec<Extension>, choice: usize) -> Result<Extension, Error> {
+    if choice == 0 || choice > extensions.len() {
+        return Err(Error::IndexOutOfBound());
+    }
+
+    let extension = extensions.swap_remove(choice - 1);
+
+    // Everything after the selection expects at least one version with a vsix to download
+    if !extension.is_available() {
+        return Err(Error::NotAvailable(extension.id()));
+    }
+
+    Ok(extension)
+}
+
+// The gallery can answer an id with a similar extension, this makes sure the right one is picked
+fn exact_match(extensions: Vec<Extension>, id: &str) -> Result<Extension, Error> {
+    match extensions
+        .iter()
+        .position(|extension| extension.id().eq_ignore_ascii_case(id))
+    {
+        Some(choice) => select_extension(extensions, choice + 1),
+        None => match extensions.first() {
+            Some(extension) => Err(Error::IdMismatch {
+                requested: id.to_string(),
+                got: extension.id(),
+            }),
+            None => Err(Error::Search(id.to_string())),
+        },
+    }
+}
+
+// Lets the user either pick an index from the current results or type a new term to query again
+async fn interactive_search(
+    client: &reqwest::Client,
+    args: &Args,
+    mut extensions: Vec<Extension>,
+    remembered: Option<&str>,
+    deadline: Option<Instant>,
+) -> Result<Extension, Error> {
+    loop {
+        let default = default_index(&extensions, remembered);
+        print_extensions(&extensions, default, args);
+
+        let line = input("Input the index of the extension you want to download, or a new search term to refine the results: ".to_owned())?;
+
+        if line.is_empty() {
+            return Err(Error::InputClosed());
+        }
+
+        let line = line.trim();
+
+        println!();
+
+        if line.is_empty() {
+            match default {
+                Some(default) => return select_extension(extensions, default + 1),
+                None => continue,
+            }
+        }
+
+        // Anything that looks like a number is an index, everything else is a new search term
+        if looks_like_index(line) {
+            match parse_index(line, extensions.len()) {
+                Ok(choice) => return select_extension(extensions, choice),
+                Err(error) => {
+                    println!("{}", error);
+                    println!();
+                    continue;
+                }
+            }
+        }
+
+        let refined = query_extensions(client, args, line, deadline).await?;
+
+        if refined.is_empty() {
+            println!("{}", Error::Search(line.to_string()));
+            println!();
+        } else {
+            extensions = refined;
+        }
+    }
+}
+
+async fn download_icon(
+    client: &reqwest::Client,
+    dir: &str,
+    extension: &Extension,
+    index: usize,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let icon_urls = extension.versions[index].asset_urls(ICON_ASSET_TYPE);
+
+    let resp = download_asset(client, &icon_urls, 0, deadline, 1).await?;
+    let icon = with_deadline(deadline, async {
+        resp.bytes().await.map_err(Error::ReqwestDns)
+    })
+    .await?;
+
+    let path = format!("{}/{}.png", dir, extension.id());
+    fs::write(&path, icon).map_err(Error::FileWrite)?;
+    println!("Saved the icon to {}", path);
+
+    Ok(())
+}
+
+// Returns how many bytes of a previous attempt can be kept, partial files that can't be trusted are discarded
+async fn prepare_resume(
+    client: &reqwest::Client,
+    urls: &[String],
+    part_path: &str,
+    deadline: Option<Instant>,
+) -> Result<u64, Error> {
+    let meta_path = format!("{}.json", part_path);
+
+    // Without an answer to the HEAD request nothing can be validated, so the download starts over
+    let current = match send_asset(client, Method::HEAD, urls, 0, None, deadline, 1).await {
+        Ok(resp) => PartialMeta::from_headers(resp.headers()),
+        Err(Error::Deadline()) => return Err(Error::Deadline()),
+        Err(_) => PartialMeta::default(),
+    };
+
+    let previous: Option<PartialMeta> = fs::read_to_string(&meta_path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<PartialSidecar>(&content).ok())
+        .map(|sidecar| sidecar.meta);
+    let size = fs::metadata(part_path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+
+    let offset = match previous {
+        _ if size == 0 => 0,
+        Some(previous) if previous != current => {
+            println!(
+                "The file changed on the server since the last attempt, restarting the download"
+            );
+            0
+        }
+        Some(_) if current.total.is_none_or(|total| size >= total) => {
+            println!(
+                "The partial download doesn't fit the file on the server, restarting the download"
+            );
+            0
+        }
+        Some(_) => size,
+        None => {
+            println!(
+                "Couldn't tell where the partial download comes from, restarting the download"
+            );
+            0
+        }
+    };
+
+    let sidecar = PartialSidecar {
+        meta: current,
+        urls: urls.to_vec(),
+    };
+    let meta = serde_json::to_string(&sidecar)
+        .map_err(|error| Error::InvalidJson(meta_path.clone(), error))?;
+    fs::write(&meta_path, meta).map_err(Error::FileWrite)?;
+
+    Ok(offset)
+}
+
+async fn download_asset(
+    client: &reqwest::Client,
+    urls: &[String],
+    offset: u64,
+    deadline: Option<Instant>,
+    attempt: u32,
+) -> Result<reqwest::Response, Error> {
+    send_asset(client, Method::GET, urls, offset, None, deadline, attempt).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use get_vsix::utility::{Publisher, Versions};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // Listed in the gallery but without any file or asset uri to download it from
+    fn extension_without_vsix() -> Extension {
+        Extension {
+            publisher: Publisher {
+                publisherId: "publisher".to_string(),
+                publisherName: "publisher".to_string(),
+                displayName: "Publisher".to_string(),
+                flags: String::new(),
+                domain: None,
+                isDomainVerified: false,
+            },
+            extensionId: "id".to_string(),
+            extensionName: "name".to_string(),
+            displayName: "name".to_string(),
+            flags: String::new(),
+            lastUpdated: String::new(),
+            publishedDate: String::new(),
+            releaseDate: String::new(),
+            shortDescription: None,
+            versions: vec![Versions {
+                version: "1.0.0".to_string(),
+                targetPlatform: None,
+                flags: String::new(),
+                lastUpdated: String::new(),
+                files: Vec::new(),
+                properties: Vec::new(),
+                assetUri: String::new(),
+                fallbackAssetUri: String::new(),
+            }],
+            statistics: Vec::new(),
+        }
+    }
+
+    // Announces more bytes than it sends, then closes the connection
+    async fn short_body_server() -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            let _ = stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nshort",
+                )
+                .await;
+            let _ = stream.shutdown().await;
+        });
+
+        format!("http://127.0.0.1:{}/ext.vsix", port)
+    }
+
+    #[tokio::test]
+    async fn short_body_is_an_incomplete_download() {
+        let args = Args::parse_from(["get-vsix", "test", "--no-preflight-disk-space", "-q"]);
+        let client = reqwest::Client::new();
+        let filename = format!("short-body-{}.vsix", std::process::id());
+        let url = short_body_server().await;
+
+        let result = download_file(&client, &args, &[url], &filename, None, None).await;
+
+        assert!(
+            matches!(
+                result,
+                Err(Error::IncompleteDownload {
+                    expected: 100,
+                    got: 5
+                })
+            ),
+            "{:?}",
+            result
+        );
+
+        let left = fs::read_dir(env::temp_dir())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name().to_string_lossy().ends_with(&filename));
+        assert!(!left, "the temp file wasn't removed");
+    }
+
+    #[tokio::test]
+    async fn resolving_a_version_without_vsix_is_an_error() {
+        let args = Args::parse_from(["get-vsix", "test", "--resolve-only"]);
+        let client = reqwest::Client::new();
+
+        let result = resolve(&client, &args, &extension_without_vsix(), 0, None).await;
+
+        assert!(matches!(result, Err(Error::NoAssets())), "{:?}", result);
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::BTreeMap;
+
+use futures::future::join_all;
+use reqwest::header::HeaderValue;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::gallery::{authorize, request_outcome};
+use crate::telemetry::record_request;
+use crate::utility::{
+    is_newer_version, Error, Extension, Files, FilterType, Properties, Publisher, RequestOptions,
+    Statistic, Versions, ENGINE_PROPERTY, EXTENSION_DEPENDENCIES_PROPERTY, EXTENSION_PACK_PROPERTY,
+    ICON_ASSET_TYPE, PRE_RELEASE_PROPERTY, SHA256_ASSET_TYPE, VSIX_ASSET_TYPE,
+};
+
+pub const DEFAULT_API_URL: &str = "https://open-vsx.org/api";
+// The copy of the marketplace api Open VSX serves, see GALLERY_PATH
+pub const GALLERY_API_URL: &str = "https://open-vsx.org/vscode/gallery/extensionquery";
+
+// Open VSX also serves a copy of the marketplace api under this path, the REST api sits next to it
+const GALLERY_PATH: &str = "/vscode/gallery/extensionquery";
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct SearchResult {
+    extensions: Vec<SearchEntry>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct SearchEntry {
+    namespace: String,
+    name: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct ExtensionJson {
+    namespace: String,
+    name: String,
+    version: String,
+    target_platform: Option<String>,
+    pre_release: bool,
+    timestamp: String,
+    verified: bool,
+    display_name: Option<String>,
+    namespace_display_name: Option<String>,
+    description: Option<String>,
+    download_count: u64,
+    average_rating: Option<f64>,
+    files: BTreeMap<String, String>,
+    downloads: BTreeMap<String, String>,
+    engines: BTreeMap<String, String>,
+    all_versions: BTreeMap<String, String>,
+    all_target_platform_versions: Vec<PlatformVersions>,
+    bundled_extensions: Vec<ExtensionReference>,
+    dependencies: Vec<ExtensionReference>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct PlatformVersions {
+    version: String,
+    target_platforms: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ExtensionReference {
+    namespace: String,
+    extension: String,
+}
+
+// The root of the REST api, the url of the registry itself or of its marketplace copy is accepted too
+pub fn api_root(api_url: &str) -> String {
+    let api_url = api_url.trim_end_matches('/');
+    let host = api_url.strip_suffix(GALLERY_PATH).unwrap_or(api_url);
+
+    if host.ends_with("/api") {
+        host.to_string()
+    } else {
+        format!("{}/api", host)
+    }
+}
+
+// Answers the same queries the marketplace does: a name criterion is looked up as a publisher.name id,
+// any other text criterion is searched, the criteria the REST api has no equivalent for are ignored
+pub async fn query(
+    client: &reqwest::Client,
+    api_url: &str,
+    options: &RequestOptions,
+    attempt: u32,
+    token: Option<&HeaderValue>,
+) -> Result<Vec<Extension>, Error> {
+    let root = api_root(api_url);
+    let Some(filter) = options.filters.first() else {
+        return Ok(Vec::new());
+    };
+
+    let text_filters = [
+        FilterType::SearchText as i8,
+        FilterType::DisplayName as i8,
+        FilterType::Tag as i8,
+        FilterType::Category as i8,
+        FilterType::PublisherName as i8,
+        FilterType::PublisherDisplayName as i8,
+        FilterType::ExtensionName as i8,
+    ];
+
+    if let Some(id) = filter
+        .criteria
+        .iter()
+        .find(|criterion| criterion.filterType == FilterType::Name as i8)
+    {
+        return Ok(get(client, &root, &id.value, attempt, token)
+            .await?
+            .into_iter()
+            .collect());
+    }
+
+    let text = filter
+        .criteria
+        .iter()
+        .find(|criterion| text_filters.contains(&criterion.filterType))
+        .map(|criterion| criterion.value.as_str())
+        .unwrap_or_default();
+
+    search(client, &root, text, filter.pageSize, attempt, token).await
+}
+
+// The search only lists names, so the details of every result are fetched for their versions
+pub async fn search(
+    client: &reqwest::Client,
+    root: &str,
+    text: &str,
+    size: i16,
+    attempt: u32,
+    token: Option<&HeaderValue>,
+) -> Result<Vec<Extension>, Error> {
+    let url = format!("{}/-/search", root);
+    let request = client
+        .get(&url)
+        .query(&[("query", text), ("size", &size.to_string())]);
+    let result: SearchResult = send(request, &url, root, attempt, token)
+        .await?
+        .unwrap_or_default();
+
+    let details = join_all(
+        result
+            .extensions
+            .iter()
+            .map(|entry| details(client, root, &entry.namespace, &entry.name, attempt, token)),
+    )
+    .await;
+
+    // One result that fails to load doesn't hide the others, unless none of them could be loaded
+    let mut extensions = Vec::new();
+    let mut first_error = None;
+
+    for (entry, result) in result.extensions.iter().zip(details) {
+        match result {
+            Ok(extension) => extensions.extend(extension),
+            Err(error) => {
+                eprintln!(
+                    "Couldn't get the details of {}.{}, leaving it out: {}",
+                    entry.namespace, entry.name, error
+                );
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+
+    match first_error {
+        Some(error) if extensions.is_empty() => Err(error),
+        _ => Ok(extensions),
+    }
+}
+
+// None when the registry doesn't know the id
+pub async fn get(
+    client: &reqwest::Client,
+    root: &str,
+    id: &str,
+    attempt: u32,
+    token: Option<&HeaderValue>,
+) -> Result<Option<Extension>, Error> {
+    match id.trim().split_once('.') {
+        Some((namespace, name)) => details(client, root, namespace, name, attempt, token).await,
+        None => Ok(None),
+    }
+}
+
+async fn details(
+    client: &reqwest::Client,
+    root: &str,
+    namespace: &str,
+    name: &str,
+    attempt: u32,
+    token: Option<&HeaderValue>,
+) -> Result<Option<Extension>, Error> {
+    let url = format!("{}/{}/{}", root, namespace, name);
+    let details: Option<ExtensionJson> = send(client.get(&url), &url, root, attempt, token).await?;
+
+    Ok(details.map(|details| into_extension(root, details)))
+}
+
+async fn send<T: DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+    url: &str,
+    root: &str,
+    attempt: u32,
+    token: Option<&HeaderValue>,
+) -> Result<Option<T>, Error> {
+    let start = Instant::now();
+    let resp = authorize(request, url, root, token).send().await;
+    record_request(
+        "query",
+        url,
+        attempt,
+        request_outcome(&resp),
+        start.elapsed(),
+    );
+    let resp = resp.map_err(Error::ReqwestDns)?;
+
+    let status = resp.status();
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    let body = resp.text().await.map_err(Error::JsonParse)?;
+    if !status.is_success() {
+        return Err(Error::Query(status.to_string(), body));
+    }
+
+    serde_json::from_str(&body)
+        .map(Some)
+        .map_err(|error| Error::InvalidJson("the Open VSX response".to_string(), error))
+}
+
+// Only the latest version comes with its download urls and properties, the urls of the others
+// follow the layout the registry always uses for them
+fn into_extension(root: &str, details: ExtensionJson) -> Extension {
+    let id = format!("{}.{}", details.namespace, details.name);
+    let file_url = |version: &str, platform: Option<&str>| match platform {
+        Some(platform) => format!(
+            "{}/{}/{}/{}/{}/file/{}-{}@{}.vsix",
+            root, details.namespace, details.name, platform, version, id, version, platform
+        ),
+        None => format!(
+            "{}/{}/{}/{}/file/{}-{}.vsix",
+            root, details.namespace, details.name, version, id, version
+        ),
+    };
+
+    let mut builds: Vec<(String, Option<String>)> = details
+        .all_target_platform_versions
+        .iter()
+        .flat_map(|entry| {
+            entry
+                .target_platforms
+                .iter()
+                .map(|platform| (entry.version.clone(), Some(platform.clone())))
+        })
+        .collect();
+
+    if builds.is_empty() {
+        builds = details
+            .all_versions
+            .keys()
+            .filter(|version| !matches!(version.as_str(), "latest" | "pre-release"))
+            .map(|version| (version.clone(), None))
+            .collect();
+    }
+
+    if builds.is_empty() {
+        builds.push((details.version.clone(), details.target_platform.clone()));
+    }
+
+    let mut versions: Vec<Versions> = builds
+        .into_iter()
+        .map(|(version, platform)| {
+            let latest = version == details.version;
+            let platform = platform.filter(|platform| platform != "universal");
+            let own_build = platform.as_deref()
+                == details
+                    .target_platform
+                    .as_deref()
+                    .filter(|platform| *platform != "universal");
+
+            let source = details
+                .downloads
+                .get(platform.as_deref().unwrap_or("universal"))
+                .or(details.files.get("download").filter(|_| own_build))
+                .filter(|_| latest)
+                .cloned()
+                .unwrap_or_else(|| file_url(&version, platform.as_deref()));
+
+            let mut files = vec![Files {
+                assetType: VSIX_ASSET_TYPE.to_string(),
+                source,
+            }];
+
+            if let (true, Some(icon)) = (latest, details.files.get("icon")) {
+                files.push(Files {
+                    assetType: ICON_ASSET_TYPE.to_string(),
+                    source: icon.clone(),
+                });
+            }
+
+            // The hash is the one of the build the details describe
+            if let (true, Some(sha256)) = (latest && own_build, details.files.get("sha256")) {
+                files.push(Files {
+                    assetType: SHA256_ASSET_TYPE.to_string(),
+                    source: sha256.clone(),
+                });
+            }
+
+            Versions {
+                targetPlatform: platform.and_then(|platform| platform.parse().ok()),
+                flags: String::new(),
+                lastUpdated: if latest {
+                    details.timestamp.clone()
+                } else {
+                    String::new()
+                },
+                files,
+                properties: if latest {
+                    latest_properties(&details)
+                } else if !details.pre_release && is_newer_version(&version, &details.version) {
+                    // The registry's latest version is the newest stable one, anything newer is a pre-release
+                    vec![Properties {
+                        key: PRE_RELEASE_PROPERTY.to_string(),
+                        value: "true".to_string(),
+                    }]
+                } else {
+                    Vec::new()
+                },
+                assetUri: String::new(),
+                fallbackAssetUri: String::new(),
+                version,
+            }
+        })
+        .collect();
+
+    versions.sort_by(|a, b| {
+        if is_newer_version(&a.version, &b.version) {
+            std::cmp::Ordering::Less
+        } else if is_newer_version(&b.version, &a.version) {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    });
+
+    let mut statistics = vec![Statistic {
+        statisticName: "install".to_string(),
+        value: details.download_count as f64,
+    }];
+    if let Some(rating) = details.average_rating {
+        statistics.push(Statistic {
+            statisticName: "averagerating".to_string(),
+            value: rating,
+        });
+    }
+
+    Extension {
+        publisher: Publisher {
+            publisherId: details.namespace.clone(),
+            publisherName: details.namespace.clone(),
+            displayName: details
+                .namespace_display_name
+                .clone()
+                .unwrap_or_else(|| details.namespace.clone()),
+            flags: if details.verified {
+                "verified".to_string()
+            } else {
+                String::new()
+            },
+            domain: None,
+            isDomainVerified: false,
+        },
+        extensionId: id,
+        extensionName: details.name.clone(),
+        displayName: details
+            .display_name
+            .clone()
+            .unwrap_or_else(|| details.name.clone()),
+        flags: "public".to_string(),
+        lastUpdated: details.timestamp.clone(),
+        publishedDate: details.timestamp.clone(),
+        releaseDate: details.timestamp.clone(),
+        shortDescription: details.description.clone(),
+        versions,
+        statistics,
+    }
+}
+
+fn latest_properties(details: &ExtensionJson) -> Vec<Properties> {
+    let property = |key: &str, value: String| Properties {
+        key: key.to_string(),
+        value,
+    };
+    let ids = |references: &[ExtensionReference]| {
+        references
+            .iter()
+            .map(|reference| format!("{}.{}", reference.namespace, reference.extension))
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+
+    let mut properties = Vec::new();
+
+    if let Some(engine) = details.engines.get("vscode") {
+        properties.push(property(ENGINE_PROPERTY, engine.clone()));
+    }
+    if details.pre_release {
+        properties.push(property(PRE_RELEASE_PROPERTY, "true".to_string()));
+    }
+    if !details.bundled_extensions.is_empty() {
+        properties.push(property(
+            EXTENSION_PACK_PROPERTY,
+            ids(&details.bundled_extensions),
+        ));
+    }
+    if !details.dependencies.is_empty() {
+        properties.push(property(
+            EXTENSION_DEPENDENCIES_PROPERTY,
+            ids(&details.dependencies),
+        ));
+    }
+
+    properties
+}
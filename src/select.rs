@@ -0,0 +1,144 @@
+use std::io::{self, Write};
+
+use crossterm::cursor::{Hide, MoveToColumn, MoveUp, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType};
+use crossterm::{execute, queue};
+
+use get_vsix::utility::Error;
+
+// Leaves raw mode even when the selector returns early with an error
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> Result<RawMode, Error> {
+        terminal::enable_raw_mode().map_err(Error::Selector)?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(io::stdout(), Show);
+    }
+}
+
+// Picks one of the items with the arrow keys, typing filters them. Gives back the position of the
+// item in the list it was given, or None when the selection is cancelled with Esc or Ctrl-C
+pub fn select(items: &[String], default: Option<usize>) -> Result<Option<usize>, Error> {
+    let _raw_mode = RawMode::enable()?;
+    let mut stdout = io::stdout();
+
+    let mut filter = String::new();
+    let mut cursor = default.unwrap_or(0);
+    let mut offset = 0;
+    let mut drawn = 0;
+
+    loop {
+        let matches: Vec<usize> = items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.to_lowercase().contains(&filter.to_lowercase()))
+            .map(|(i, _)| i)
+            .collect();
+
+        cursor = cursor.min(matches.len().saturating_sub(1));
+
+        // The prompt takes a line, the rest of the terminal is for the items. Lines are cut to
+        // the width so none wraps, which would throw off the redraw. A terminal that doesn't
+        // know its size reports zero
+        let (columns, rows) = terminal::size()
+            .ok()
+            .filter(|(columns, rows)| *columns > 0 && *rows > 0)
+            .map(|(columns, rows)| (columns as usize, rows as usize))
+            .unwrap_or((80, 24));
+        let rows = rows.saturating_sub(2).max(1);
+        let fit = |line: String| {
+            line.chars()
+                .take(columns.saturating_sub(1))
+                .collect::<String>()
+        };
+
+        if cursor < offset {
+            offset = cursor;
+        } else if cursor >= offset + rows {
+            offset = cursor + 1 - rows;
+        }
+
+        if drawn > 0 {
+            queue!(stdout, MoveUp(drawn as u16)).map_err(Error::Selector)?;
+        }
+        queue!(
+            stdout,
+            Hide,
+            MoveToColumn(0),
+            Clear(ClearType::FromCursorDown),
+            Print(fit(format!(
+                "Pick an extension (arrows, Enter, Esc, type to filter): {}",
+                filter
+            ))),
+            Print("\r\n")
+        )
+        .map_err(Error::Selector)?;
+
+        for (line, i) in matches.iter().enumerate().skip(offset).take(rows) {
+            if line == cursor {
+                queue!(
+                    stdout,
+                    SetAttribute(Attribute::Reverse),
+                    Print(fit(format!("> {}", items[*i]))),
+                    SetAttribute(Attribute::Reset),
+                    Print("\r\n")
+                )
+            } else {
+                queue!(
+                    stdout,
+                    Print(fit(format!("  {}", items[*i]))),
+                    Print("\r\n")
+                )
+            }
+            .map_err(Error::Selector)?;
+        }
+
+        if matches.is_empty() {
+            queue!(stdout, Print("  (no match)\r\n")).map_err(Error::Selector)?;
+        }
+
+        stdout.flush().map_err(Error::Selector)?;
+        drawn = 1 + matches.len().clamp(1, rows);
+
+        let Event::Key(key) = event::read().map_err(Error::Selector)? else {
+            continue;
+        };
+
+        // Windows also reports the releases
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+            KeyCode::Enter => match matches.get(cursor) {
+                Some(i) => return Ok(Some(*i)),
+                None => continue,
+            },
+            KeyCode::Up => cursor = cursor.saturating_sub(1),
+            KeyCode::Down => cursor = (cursor + 1).min(matches.len().saturating_sub(1)),
+            KeyCode::PageUp => cursor = cursor.saturating_sub(rows),
+            KeyCode::PageDown => cursor = (cursor + rows).min(matches.len().saturating_sub(1)),
+            KeyCode::Home => cursor = 0,
+            KeyCode::End => cursor = matches.len().saturating_sub(1),
+            KeyCode::Backspace => {
+                filter.pop();
+            }
+            KeyCode::Char(c) => {
+                filter.push(c);
+                cursor = 0;
+            }
+            _ => {}
+        }
+    }
+}
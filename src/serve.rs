@@ -0,0 +1,273 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use get_vsix::utility::{format_size, Error};
+use get_vsix::vsix::read_package_json;
+
+// Requests are only a request line and a few headers, anything bigger isn't meant for us
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+#[derive(Serialize, Debug)]
+struct IndexEntry {
+    file: String,
+    publisher: String,
+    name: String,
+    version: String,
+    size: u64,
+}
+
+// Serves the vsix files of the folder, with an html listing on / and the same listing as json on /index.json
+pub async fn serve(dir: &str, host: &str, port: u16) -> Result<(), Error> {
+    let listener = TcpListener::bind((host, port))
+        .await
+        .map_err(Error::Serve)?;
+
+    println!(
+        "Serving the extensions of {} on http://{}:{}/",
+        dir, host, port
+    );
+
+    loop {
+        let (stream, peer) = listener.accept().await.map_err(Error::Serve)?;
+        let dir = dir.to_string();
+
+        tokio::spawn(async move {
+            if let Err(error) = handle(stream, &dir).await {
+                eprintln!("{}: {}", peer, error);
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, dir: &str) -> Result<(), Error> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await.map_err(Error::Serve)?;
+        if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or("/"),
+    );
+    let head = method == "HEAD";
+
+    println!("{} {}", method, target);
+
+    if method != "GET" && !head {
+        return respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"",
+            head,
+        )
+        .await;
+    }
+
+    let path = percent_decode(target.split('?').next().unwrap_or_default());
+
+    // The index is only built for the listings, a download doesn't need to look at the other files
+    match path.as_str() {
+        "/" => {
+            let html = index_html(&read_index(dir));
+            respond(
+                &mut stream,
+                "200 OK",
+                "text/html; charset=utf-8",
+                html.as_bytes(),
+                head,
+            )
+            .await
+        }
+        "/index.json" => {
+            let json = serde_json::to_string_pretty(&read_index(dir))
+                .map_err(|error| Error::InvalidJson("the index".to_string(), error))?;
+            respond(
+                &mut stream,
+                "200 OK",
+                "application/json",
+                json.as_bytes(),
+                head,
+            )
+            .await
+        }
+        path => {
+            // Only a vsix directly in the folder is served, so a path can never leave it
+            let name = path.trim_start_matches('/');
+            let file = Path::new(dir).join(name);
+            let servable = !name.contains(['/', '\\']) && name.ends_with(".vsix") && file.is_file();
+
+            if servable {
+                send_file(&mut stream, &file, head).await
+            } else {
+                respond(
+                    &mut stream,
+                    "404 Not Found",
+                    "text/plain",
+                    b"Not found",
+                    head,
+                )
+                .await
+            }
+        }
+    }
+}
+
+// The details come from the sidecar written by --metadata-sidecar, the manifest of the vsix is only
+// read when there is none. A vsix that can't be read is still listed, only without its details
+fn read_index(dir: &str) -> Vec<IndexEntry> {
+    let mut entries: Vec<IndexEntry> = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .path()
+                    .extension()
+                    .is_some_and(|extension| extension == "vsix")
+            })
+            .map(|entry| {
+                let path = entry.path();
+                let package = read_sidecar(&path)
+                    .or_else(|| read_package_json(&path.to_string_lossy()).ok())
+                    .unwrap_or_default();
+                let field = |key: &str| package[key].as_str().unwrap_or("-").to_string();
+
+                IndexEntry {
+                    file: entry.file_name().to_string_lossy().to_string(),
+                    publisher: field("publisher"),
+                    name: field("name"),
+                    version: field("version"),
+                    size: entry.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+                }
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    entries.sort_by(|a, b| a.file.cmp(&b.file));
+    entries
+}
+
+fn read_sidecar(path: &Path) -> Option<serde_json::Value> {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".json");
+
+    let json = fs::read_to_string(sidecar).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+fn index_html(entries: &[IndexEntry]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Extensions</title></head>\n<body>\n<h1>Extensions</h1>\n<ul>\n",
+    );
+
+    for entry in entries {
+        html += &format!(
+            "<li><a href=\"/{}\">{}</a> {}.{} v{} ({})</li>\n",
+            escape_html(&entry.file),
+            escape_html(&entry.file),
+            escape_html(&entry.publisher),
+            escape_html(&entry.name),
+            escape_html(&entry.version),
+            format_size(entry.size as usize)
+        );
+    }
+
+    html + "</ul>\n</body>\n</html>\n"
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+    head: bool,
+) -> Result<(), Error> {
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+
+    stream
+        .write_all(header.as_bytes())
+        .await
+        .map_err(Error::Serve)?;
+    if !head {
+        stream.write_all(body).await.map_err(Error::Serve)?;
+    }
+
+    stream.shutdown().await.map_err(Error::Serve)
+}
+
+async fn send_file(stream: &mut TcpStream, path: &Path, head: bool) -> Result<(), Error> {
+    let mut file = tokio::fs::File::open(path).await.map_err(Error::FileRead)?;
+    let size = file.metadata().await.map_err(Error::FileRead)?.len();
+
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/vsix\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        size
+    );
+
+    stream
+        .write_all(header.as_bytes())
+        .await
+        .map_err(Error::Serve)?;
+    if !head {
+        tokio::io::copy(&mut file, stream)
+            .await
+            .map_err(Error::Serve)?;
+    }
+
+    stream.shutdown().await.map_err(Error::Serve)
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
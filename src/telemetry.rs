@@ -0,0 +1,161 @@
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use tokio::time::Instant;
+
+use crate::utility::format_size;
+
+// Every request of the run is recorded here so --verbose can print them once the run is over
+static TELEMETRY: Mutex<Telemetry> = Mutex::new(Telemetry::new());
+
+struct Telemetry {
+    lookups: Vec<Lookup>,
+    requests: Vec<RequestTiming>,
+}
+
+struct Lookup {
+    host: String,
+    duration: Duration,
+}
+
+struct RequestTiming {
+    kind: &'static str,
+    url: String,
+    attempt: u32,
+    outcome: String,
+    first_byte: Duration,
+    transfer: Option<Duration>,
+    bytes: Option<u64>,
+}
+
+impl Telemetry {
+    const fn new() -> Telemetry {
+        Telemetry {
+            lookups: Vec::new(),
+            requests: Vec::new(),
+        }
+    }
+}
+
+fn with_telemetry(f: impl FnOnce(&mut Telemetry)) {
+    if let Ok(mut telemetry) = TELEMETRY.lock() {
+        f(&mut telemetry);
+    }
+}
+
+// Points at a recorded request, so its transfer can be completed once the body has been read
+// even when other requests were recorded in the meantime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestId(usize);
+
+// The attempt is 1 the first time an operation is tried, and counts up with each retry of it.
+// The time to first byte also covers connecting, reqwest doesn't expose the two separately
+pub fn record_request(
+    kind: &'static str,
+    url: &str,
+    attempt: u32,
+    outcome: String,
+    first_byte: Duration,
+) -> RequestId {
+    let mut id = RequestId(usize::MAX);
+
+    with_telemetry(|telemetry| {
+        id = RequestId(telemetry.requests.len());
+        telemetry.requests.push(RequestTiming {
+            kind,
+            url: url.to_string(),
+            attempt,
+            outcome,
+            first_byte,
+            transfer: None,
+            bytes: None,
+        })
+    });
+
+    id
+}
+
+// The request a response was recorded as, set on every response send_asset gives back
+pub fn request_id(resp: &reqwest::Response) -> Option<RequestId> {
+    resp.extensions().get::<RequestId>().copied()
+}
+
+pub fn record_transfer(request: RequestId, bytes: u64, transfer: Duration) {
+    with_telemetry(|telemetry| {
+        if let Some(request) = telemetry.requests.get_mut(request.0) {
+            request.bytes = Some(bytes);
+            request.transfer = Some(transfer);
+        }
+    });
+}
+
+pub fn print_telemetry() {
+    with_telemetry(|telemetry| {
+        eprintln!();
+        eprintln!("Timings:");
+
+        for lookup in &telemetry.lookups {
+            eprintln!("\tDNS {}: {} ms", lookup.host, lookup.duration.as_millis());
+        }
+
+        for kind in ["query", "download"] {
+            let requests: Vec<&RequestTiming> = telemetry
+                .requests
+                .iter()
+                .filter(|request| request.kind == kind)
+                .collect();
+
+            if requests.is_empty() {
+                continue;
+            }
+
+            eprintln!(
+                "\t{} {} request(s), {} of them retries",
+                requests.len(),
+                kind,
+                requests
+                    .iter()
+                    .filter(|request| request.attempt > 1)
+                    .count()
+            );
+
+            for request in requests {
+                eprintln!("\t\t{} {}", request.outcome, request.url);
+                eprintln!(
+                    "\t\t\tTime to first byte: {} ms",
+                    request.first_byte.as_millis()
+                );
+
+                if let Some(transfer) = request.transfer {
+                    eprintln!("\t\t\tTransfer: {} ms", transfer.as_millis());
+                }
+
+                if let Some(bytes) = request.bytes {
+                    eprintln!("\t\t\tReceived: {}", format_size(bytes as usize));
+                }
+            }
+        }
+    });
+}
+
+// Resolves like the system resolver but records how long each lookup took
+pub struct TimedResolver;
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let start = Instant::now();
+
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+
+            let duration = start.elapsed();
+            with_telemetry(|telemetry| telemetry.lookups.push(Lookup { host, duration }));
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
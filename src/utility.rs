@@ -1,10 +1,35 @@
+use clap::ValueEnum;
+use filedescriptor::{FileDescriptor, StdioDescriptor};
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, ETAG, LAST_MODIFIED};
 use serde::{Deserialize, Serialize};
-use std::io::Write;
-use std::num::ParseIntError;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{env, fs, io};
 use thiserror::Error;
+use tokio::time::Instant;
+
+pub const VSIX_ASSET_TYPE: &str = "Microsoft.VisualStudio.Services.VSIXPackage";
+pub const PRE_RELEASE_PROPERTY: &str = "Microsoft.VisualStudio.Code.PreRelease";
+pub const EXTENSION_PACK_PROPERTY: &str = "Microsoft.VisualStudio.Code.ExtensionPack";
+pub const EXTENSION_DEPENDENCIES_PROPERTY: &str =
+    "Microsoft.VisualStudio.Code.ExtensionDependencies";
+pub const ENGINE_PROPERTY: &str = "Microsoft.VisualStudio.Code.Engine";
+// The marketplace doesn't publish hashes, Open VSX does and its sha256 file is listed under this type
+pub const SHA256_ASSET_TYPE: &str = "Microsoft.VisualStudio.Services.VSIXPackage.Sha256";
+pub const ICON_ASSET_TYPE: &str = "Microsoft.VisualStudio.Services.Icons.Default";
+
+#[cfg(all(feature = "clipboard", target_os = "linux"))]
+const CLIPBOARD_LINGER: Duration = Duration::from_secs(2);
+
+// ssh exits with this when it can't connect or log in, any other failure comes from the command
+const SSH_CONNECTION_FAILED: i32 = 255;
 
 pub fn format_size(size: usize) -> String {
     if size / 1000 / 1000 > 0 {
@@ -16,19 +41,600 @@ pub fn format_size(size: usize) -> String {
     }
 }
 
-pub fn install_extension(path: String, program: String) -> Result<(), Error> {
-    Command::new(program)
-        .arg("--install-extension")
+// How far back the speed is averaged, long enough to smooth the bursts of a connection
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+// How often a line is printed when the output isn't a terminal
+const PROGRESS_LINE_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_BAR_WIDTH: usize = 100 / 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ProgressMode {
+    Bar,
+    Lines,
+    Hidden,
+}
+
+// Redraws the progress of a download on a single line that fits the terminal. When stdout isn't
+// a terminal it prints a line every few seconds instead, or nothing at all when quiet
+pub struct ProgressBar {
+    total: u64,
+    style: BarStyle,
+    colored: bool,
+    mode: ProgressMode,
+    samples: VecDeque<(Instant, u64)>,
+    last_line: Option<Instant>,
+}
+
+impl ProgressBar {
+    // The offset is what a resumed download already had, it doesn't count towards the speed
+    pub fn new(
+        total: u64,
+        offset: u64,
+        style: BarStyle,
+        colored: bool,
+        quiet: bool,
+    ) -> ProgressBar {
+        let mode = if io::stdout().is_terminal() {
+            ProgressMode::Bar
+        } else if quiet {
+            ProgressMode::Hidden
+        } else {
+            ProgressMode::Lines
+        };
+
+        ProgressBar {
+            total,
+            style,
+            colored,
+            mode,
+            samples: VecDeque::from([(Instant::now(), offset)]),
+            last_line: None,
+        }
+    }
+
+    pub fn update(&mut self, progress: u64) -> Result<(), Error> {
+        let now = Instant::now();
+        self.samples.push_back((now, progress));
+        while self.samples.len() > 2 && now.duration_since(self.samples[0].0) > SPEED_WINDOW {
+            self.samples.pop_front();
+        }
+
+        match self.mode {
+            ProgressMode::Hidden => Ok(()),
+            ProgressMode::Lines => {
+                let finished = progress >= self.total;
+                if !finished
+                    && self
+                        .last_line
+                        .is_some_and(|line| line.elapsed() < PROGRESS_LINE_INTERVAL)
+                {
+                    return Ok(());
+                }
+                self.last_line = Some(now);
+
+                println!("{}", self.status(progress).join(" "));
+                Ok(())
+            }
+            ProgressMode::Bar => {
+                print!("\r{}{}", Ansi::ClearLine, self.line(progress));
+                io::stdout().flush().map_err(Error::Flush)
+            }
+        }
+    }
+
+    // Leaves the bar on its own line so what comes next doesn't overwrite it
+    pub fn finish(&mut self) -> Result<(), Error> {
+        let progress = self.samples.back().map(|(_, bytes)| *bytes).unwrap_or(0);
+        if progress < self.total {
+            self.update(self.total)?;
+        }
+
+        if self.mode == ProgressMode::Bar {
+            println!();
+        }
+
+        Ok(())
+    }
+
+    // Bytes per second over the last few seconds
+    fn speed(&self) -> u64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some((first, from)), Some((last, to))) => {
+                let elapsed = last.duration_since(*first).as_secs_f64();
+                if elapsed > 0.0 {
+                    (to.saturating_sub(*from) as f64 / elapsed) as u64
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn percentage(&self, progress: u64) -> f64 {
+        if self.total == 0 {
+            100.0
+        } else {
+            (progress as f64 / self.total as f64 * 100.0).min(100.0)
+        }
+    }
+
+    // The parts of the line, dropped from the end when the terminal is too narrow for them
+    fn status(&self, progress: u64) -> Vec<String> {
+        let speed = self.speed();
+        let eta = match speed {
+            0 => "--:--".to_string(),
+            speed => format_duration(self.total.saturating_sub(progress) / speed),
+        };
+
+        vec![
+            format!("{:>3}%", self.percentage(progress) as usize),
+            format!(
+                "{} / {}",
+                format_size(progress as usize),
+                format_size(self.total as usize)
+            ),
+            format!("{}/s", format_size(speed as usize)),
+            format!("ETA {}", eta),
+        ]
+    }
+
+    fn line(&self, progress: u64) -> String {
+        // A terminal that doesn't know its size reports zero
+        let columns = crossterm::terminal::size()
+            .ok()
+            .map(|(columns, _)| columns as usize)
+            .filter(|columns| *columns > 0)
+            .unwrap_or(80);
+        // The last column is left empty, writing to it wraps the line on some terminals
+        let columns = columns - 1;
+
+        let mut status = self.status(progress);
+        let mut text = status.join(" ");
+        while status.len() > 1 && text.chars().count() > columns {
+            status.pop();
+            text = status.join(" ");
+        }
+
+        let head = status.remove(0);
+        let tail = status.join(" ");
+        let used = head.chars().count() + tail.chars().count() + 4;
+        let width = columns.saturating_sub(used).min(MAX_BAR_WIDTH);
+
+        if width < 5 {
+            return text.chars().take(columns).collect();
+        }
+
+        let percentage = self.percentage(progress);
+        let bar = render_bar(percentage, self.style, width);
+        let bar = if self.colored {
+            format!("{}{}{}", bar_color(percentage), bar, Ansi::Reset)
+        } else {
+            bar
+        };
+
+        format!("{} [{}] {}", head, bar, tail)
+    }
+}
+
+// m:ss, or h:mm:ss past an hour
+fn format_duration(seconds: u64) -> String {
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+fn bar_color(percentage: f64) -> Ansi {
+    if percentage < 33.0 {
+        Ansi::Red
+    } else if percentage < 66.0 {
+        Ansi::Yellow
+    } else {
+        Ansi::Green
+    }
+}
+
+fn render_bar(percentage: f64, style: BarStyle, width: usize) -> String {
+    let filled = ((percentage / 100.0 * width as f64) as usize).min(width);
+
+    match style {
+        BarStyle::Ascii => {
+            let mut bar = "=".repeat(filled);
+            if filled < width {
+                bar += ">"
+            }
+            bar + &" ".repeat(width.saturating_sub(filled + 1))
+        }
+        BarStyle::Unicode if unicode_supported() => {
+            // Each cell is split in eighths so the bar moves smoothly between two cells
+            const PARTIAL: [&str; 8] = [" ", "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+
+            let eighths = ((percentage / 100.0 * width as f64 * 8.0) as usize).min(width * 8);
+            let mut bar = "█".repeat(eighths / 8);
+            if eighths / 8 < width {
+                bar += PARTIAL[eighths % 8];
+            }
+            bar + &" ".repeat(width.saturating_sub(eighths / 8 + 1))
+        }
+        BarStyle::Unicode => render_bar(percentage, BarStyle::Ascii, width),
+        BarStyle::Dots => ".".repeat(filled) + &" ".repeat(width - filled),
+    }
+}
+
+// X11 and Wayland only keep the text while we own the clipboard, so on Linux we linger
+// a moment for a clipboard manager or another program to take it
+#[cfg(feature = "clipboard")]
+pub fn copy_to_clipboard(text: &str) -> Result<(), Error> {
+    let mut clipboard =
+        arboard::Clipboard::new().map_err(|error| Error::Clipboard(error.to_string()))?;
+
+    #[cfg(target_os = "linux")]
+    let result = {
+        use arboard::SetExtLinux;
+        clipboard
+            .set()
+            .wait_until(std::time::Instant::now() + CLIPBOARD_LINGER)
+            .text(text)
+    };
+
+    #[cfg(not(target_os = "linux"))]
+    let result = clipboard.set_text(text);
+
+    result.map_err(|error| Error::Clipboard(error.to_string()))
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn copy_to_clipboard(_text: &str) -> Result<(), Error> {
+    Err(Error::ClipboardUnsupported())
+}
+
+// Terminals on a non UTF-8 locale would garble the block characters
+pub fn unicode_supported() -> bool {
+    if cfg!(windows) {
+        return true;
+    }
+
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|key| env::var(key).ok().filter(|value| !value.is_empty()))
+        .map(|value| {
+            let value = value.to_lowercase();
+            value.contains("utf-8") || value.contains("utf8")
+        })
+        .unwrap_or(false)
+}
+
+// Windows forbids these characters in file names, so they are replaced everywhere for consistency
+pub fn normalize_version(version: &str) -> String {
+    version
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+// Removes the comments and trailing commas that jsonc files (like the ones vscode uses) allow
+pub fn strip_jsonc(content: &str) -> String {
+    let mut stripped = String::with_capacity(content.len());
+    let mut chars = content.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            stripped.push(c);
+            match c {
+                '\\' => stripped.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                stripped.push(c);
+            }
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        stripped.push(c);
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            (',', _) => {
+                let rest = chars.clone().find(|c| !c.is_whitespace());
+                if !matches!(rest, Some('}') | Some(']')) {
+                    stripped.push(c);
+                }
+            }
+            _ => stripped.push(c),
+        }
+    }
+
+    stripped
+}
+
+pub fn install_extension(
+    path: String,
+    program: String,
+    profile: Option<&str>,
+    check_profile: bool,
+    capture: bool,
+) -> Result<(), Error> {
+    let mut command = Command::new(&program);
+
+    if let Some(profile) = profile {
+        if check_profile && !supports_profiles(&program)? {
+            return Err(Error::ProfileUnsupported(program));
+        }
+
+        // The profile has to come before --install-extension for the editor to apply it
+        command.arg("--profile").arg(profile);
+    }
+
+    command.arg("--install-extension").arg(&path).arg("--force");
+
+    if !capture {
+        let status = command
+            .status()
+            .map_err(|error| spawn_error(&program, error))?;
+
+        if !status.success() {
+            return Err(Error::Install(program, status.to_string()));
+        }
+
+        return Ok(());
+    }
+
+    let output = command
+        .output()
+        .map_err(|error| spawn_error(&program, error))?;
+
+    // Captured output is only shown when the install fails
+    if !output.status.success() {
+        // Some editors print in the encoding of the locale, lossy conversion keeps odd bytes from failing the install
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        let message = match stderr.trim() {
+            "" => stdout.trim(),
+            stderr => stderr,
+        };
+        return Err(Error::Install(program, message.to_string()));
+    }
+
+    Ok(())
+}
+
+// The editor lists one publisher.name@version per line
+pub fn list_installed_extensions(
+    program: &str,
+    profile: Option<&str>,
+) -> Result<Vec<(String, String)>, Error> {
+    let mut command = Command::new(program);
+
+    if let Some(profile) = profile {
+        command.arg("--profile").arg(profile);
+    }
+
+    let output = command
+        .arg("--list-extensions")
+        .arg("--show-versions")
+        .output()
+        .map_err(|error| spawn_error(program, error))?;
+
+    if !output.status.success() {
+        return Err(Error::ListInstalled(
+            program.to_string(),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().split_once('@'))
+        .map(|(id, version)| (id.to_string(), version.to_string()))
+        .collect())
+}
+
+// Any failure to list the extensions just means the install goes ahead
+pub fn installed_version(program: &str, profile: Option<&str>, id: &str) -> Option<String> {
+    list_installed_extensions(program, profile)
+        .ok()?
+        .into_iter()
+        .find(|(installed, _)| installed.eq_ignore_ascii_case(id))
+        .map(|(_, version)| version)
+}
+
+// Compares the numeric parts of two versions, anything after a '-' or '+' is ignored
+pub fn is_newer_version(candidate: &str, installed: &str) -> bool {
+    let parts = |version: &str| -> Vec<u64> {
+        version
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+
+    let (candidate, installed) = (parts(candidate), parts(installed));
+    let len = candidate.len().max(installed.len());
+
+    for i in 0..len {
+        let (a, b) = (
+            candidate.get(i).copied().unwrap_or(0),
+            installed.get(i).copied().unwrap_or(0),
+        );
+        if a != b {
+            return a > b;
+        }
+    }
+
+    false
+}
+
+const KNOWN_EDITORS: [&str; 7] = [
+    "code",
+    "codium",
+    "code-insiders",
+    "code-oss",
+    "code-server",
+    "cursor",
+    "windsurf",
+];
+
+// Editors from KNOWN_EDITORS that can be found in the PATH, Windows installs them as .cmd scripts
+pub fn find_editors() -> Vec<&'static str> {
+    let paths: Vec<PathBuf> = env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect())
+        .unwrap_or_default();
+    let suffixes: &[&str] = if cfg!(windows) {
+        &[".cmd", ".bat", ".exe"]
+    } else {
+        &[""]
+    };
+
+    KNOWN_EDITORS
+        .into_iter()
+        .filter(|editor| {
+            paths.iter().any(|dir| {
+                suffixes
+                    .iter()
+                    .any(|suffix| dir.join(format!("{}{}", editor, suffix)).is_file())
+            })
+        })
+        .collect()
+}
+
+// A missing program usually means another editor is installed, so the error points to it
+fn spawn_error(program: &str, error: io::Error) -> Error {
+    if error.kind() != io::ErrorKind::NotFound {
+        return Error::Command(error);
+    }
+
+    let hint = match find_editors().as_slice() {
+        [] => "no known editor was found in the PATH either".to_string(),
+        editors => format!(
+            "try {}",
+            editors
+                .iter()
+                .map(|editor| format!("--program {}", editor))
+                .collect::<Vec<_>>()
+                .join(" or ")
+        ),
+    };
+
+    Error::ProgramNotFound(program.to_string(), hint)
+}
+
+// Editors that know about profiles list the flag in their help
+fn supports_profiles(program: &str) -> Result<bool, Error> {
+    let help = Command::new(program)
+        .arg("--help")
+        .output()
+        .map_err(|error| spawn_error(program, error))?;
+
+    Ok(String::from_utf8_lossy(&help.stdout).contains("--profile"))
+}
+
+// Copies the vsix to the remote machine with scp and installs it there over ssh
+pub fn install_extension_remote(path: String, remote: &str, program: &str) -> Result<(), Error> {
+    let found = Command::new("ssh")
+        .arg(remote)
+        .arg(format!("command -v {}", shell_quote(program)))
+        .stdout(Stdio::null())
+        .status()
+        .map_err(|error| Error::RemoteCommand("ssh".to_string(), error))?;
+
+    if found.code() == Some(SSH_CONNECTION_FAILED) {
+        return Err(Error::Remote(format!("connecting to {}", remote)));
+    }
+
+    if !found.success() {
+        return Err(Error::RemoteProgram(
+            program.to_string(),
+            remote.to_string(),
+        ));
+    }
+
+    let filename = Path::new(&path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let remote_path = format!("/tmp/{}", filename);
+
+    let copied = Command::new("scp")
         .arg(&path)
-        .arg("--force")
+        .arg(format!("{}:{}", remote, shell_quote(&remote_path)))
+        .status()
+        .map_err(|error| Error::RemoteCommand("scp".to_string(), error))?;
+
+    if !copied.success() {
+        return Err(Error::Remote(format!("copying {} to {}", filename, remote)));
+    }
+
+    let installed = Command::new("ssh")
+        .arg(remote)
+        .arg(format!(
+            "{} --install-extension {} --force; status=$?; rm -f {}; exit $status",
+            shell_quote(program),
+            shell_quote(&remote_path),
+            shell_quote(&remote_path)
+        ))
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
-        .output()
-        .map_err(Error::Command)?;
+        .status()
+        .map_err(|error| Error::RemoteCommand("ssh".to_string(), error))?;
+
+    if installed.code() == Some(SSH_CONNECTION_FAILED) {
+        return Err(Error::Remote(format!("connecting to {}", remote)));
+    }
+
+    if !installed.success() {
+        return Err(Error::Remote(format!(
+            "installing {} on {}",
+            filename, remote
+        )));
+    }
 
     Ok(())
 }
 
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Some file systems can't report their free space, the download is attempted anyway on those
+pub fn check_disk_space(dir: &Path, needed: u64) -> Result<(), Error> {
+    match fs4::available_space(dir) {
+        Ok(available) if available < needed => Err(Error::InsufficientSpace { needed, available }),
+        _ => Ok(()),
+    }
+}
+
 pub fn move_to(tmp_path: String, path: String) -> Result<(), Error> {
     match fs::rename(&tmp_path, &path) {
         Ok(_) => println!("Moved file to {}", &path),
@@ -44,6 +650,199 @@ pub fn move_to(tmp_path: String, path: String) -> Result<(), Error> {
     Ok(())
 }
 
+// Trust on first use: the first hash seen for a key is recorded, later ones must match it
+pub fn check_lockfile(lockfile: &str, key: &str, sha256: &str) -> Result<(), Error> {
+    let mut hashes: BTreeMap<String, String> = match fs::read_to_string(lockfile) {
+        Ok(content) => serde_json::from_str(&content).map_err(Error::Lockfile)?,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(error) => return Err(Error::FileRead(error)),
+    };
+
+    match hashes.get(key) {
+        Some(expected) if expected != sha256 => Err(Error::ChecksumMismatch {
+            key: key.to_string(),
+            expected: expected.clone(),
+            got: sha256.to_string(),
+        }),
+        Some(_) => Ok(()),
+        None => {
+            hashes.insert(key.to_string(), sha256.to_string());
+            let content = serde_json::to_string_pretty(&hashes).map_err(Error::Lockfile)?;
+            fs::write(lockfile, content).map_err(Error::FileWrite)?;
+            println!("{} Recorded the hash of {} in {}", Glyph::Ok, key, lockfile);
+            Ok(())
+        }
+    }
+}
+
+// Lines are "<hash>  <file name>", sha256sum writes a '*' before the name in binary mode
+pub fn check_checksum_file(checksum_file: &str, filename: &str, sha256: &str) -> Result<(), Error> {
+    let content = fs::read_to_string(checksum_file).map_err(Error::FileRead)?;
+
+    let expected = content
+        .lines()
+        .filter_map(|line| line.trim().split_once(char::is_whitespace))
+        .find(|(_, name)| name.trim_start().trim_start_matches('*') == filename)
+        .map(|(hash, _)| hash.to_lowercase())
+        .ok_or_else(|| Error::MissingChecksum(filename.to_string(), checksum_file.to_string()))?;
+
+    if expected != sha256 {
+        return Err(Error::ChecksumMismatch {
+            key: filename.to_string(),
+            expected,
+            got: sha256.to_string(),
+        });
+    }
+
+    println!(
+        "{} Verified the hash of {} against {}",
+        Glyph::Ok,
+        filename,
+        checksum_file
+    );
+
+    Ok(())
+}
+
+// Two processes downloading the same extension would overwrite each other's file without the pid and time
+pub fn unique_tmp_path(filename: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos())
+        .unwrap_or_default();
+
+    format!(
+        "{}/{}-{}-{}",
+        env::temp_dir().display(),
+        std::process::id(),
+        nanos,
+        filename
+    )
+}
+
+// Defaults for the flags that are the same on every run, the command line wins over them
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub api: Option<String>,
+    pub program: Option<String>,
+    pub output: Option<String>,
+    pub limit: Option<i16>,
+    pub target_platform: Option<String>,
+    pub proxy: Option<String>,
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("get-vsix").join("config.toml"))
+}
+
+// Without a path the default config is read, and not having one is the same as an empty config
+pub fn read_config(path: Option<&Path>) -> Result<Config, Error> {
+    let content = match path {
+        Some(path) => fs::read_to_string(path).map_err(Error::FileRead)?,
+        None => match config_path().and_then(|path| fs::read_to_string(path).ok()) {
+            Some(content) => content,
+            None => return Ok(Config::default()),
+        },
+    };
+
+    toml::from_str(&content).map_err(|error| {
+        let path = path.map(Path::to_path_buf).or_else(config_path);
+        Error::InvalidConfig(
+            path.map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            error,
+        )
+    })
+}
+
+fn history_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("get-vsix").join("history.json"))
+}
+
+pub fn history_key(search: &str) -> String {
+    search.trim().to_lowercase()
+}
+
+// A missing or unreadable history only means there is nothing to suggest
+pub fn read_history() -> BTreeMap<String, String> {
+    history_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_history(
+    mut history: BTreeMap<String, String>,
+    search: &str,
+    id: &str,
+) -> Result<(), Error> {
+    let path = match history_path() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    if history.get(search).map(String::as_str) == Some(id) {
+        return Ok(());
+    }
+    history.insert(search.to_string(), id.to_string());
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(Error::FileWrite)?;
+    }
+
+    let content = serde_json::to_string_pretty(&history).map_err(Error::History)?;
+    fs::write(path, content).map_err(Error::FileWrite)
+}
+
+fn strip_index(line: &str) -> &str {
+    let line = line.trim();
+    let line = line.strip_prefix('#').unwrap_or(line);
+    let line = match line.strip_prefix('[') {
+        Some(inner) => inner.strip_suffix(']').unwrap_or(inner),
+        None => line,
+    };
+
+    line.trim()
+}
+
+pub fn looks_like_index(line: &str) -> bool {
+    let index = strip_index(line);
+    !index.is_empty() && index.chars().all(|c| c.is_ascii_digit())
+}
+
+// Ids are publisher.name, neither part can be empty or contain spaces
+pub fn is_extension_id(search: &str) -> bool {
+    match search.trim().split_once('.') {
+        Some((publisher, name)) => {
+            !publisher.is_empty()
+                && !name.is_empty()
+                && !search.trim().contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+// Accepts the index the way the list shows it, so "[2]" and "#2" both mean 2
+pub fn parse_index(line: &str, len: usize) -> Result<usize, Error> {
+    match strip_index(line).parse() {
+        Ok(choice) if (1..=len).contains(&choice) => Ok(choice),
+        _ => Err(Error::InvalidIndex(line.trim().to_string(), len)),
+    }
+}
+
+// Azure DevOps answers 400 with a VssVersionOutOfRangeException or a similar message about the api-version
+pub fn is_api_version_error(status: reqwest::StatusCode, body: &str) -> bool {
+    let body = body.to_lowercase();
+
+    matches!(
+        status,
+        reqwest::StatusCode::BAD_REQUEST | reqwest::StatusCode::NOT_FOUND
+    ) && (body.contains("versionoutofrange")
+        || body.contains("api-version")
+        || body.contains("api version"))
+}
+
 pub fn input(prompt: String) -> Result<String, Error> {
     print!("{}", prompt);
     std::io::stdout().flush().map_err(Error::Flush)?;
@@ -56,14 +855,56 @@ pub fn input(prompt: String) -> Result<String, Error> {
     Ok(choice)
 }
 
+// Runs the future but gives up with Error::Deadline once the deadline is reached
+pub async fn with_deadline<T>(
+    deadline: Option<Instant>,
+    future: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future)
+            .await
+            .map_err(|_| Error::Deadline())?,
+        None => future.await,
+    }
+}
+
+// Asks a yes or no question, pressing Enter without typing anything picks the default,
+// which is the capitalized letter of [Y/n] or [y/N]
+pub fn confirm(question: &str, default: bool) -> Result<bool, Error> {
+    let choices = if default { "[Y/n]" } else { "[y/N]" };
+    Ok(is_yes(
+        &input(format!("{} {}: ", question, choices))?,
+        default,
+    ))
+}
+
+pub fn is_yes(answer: &str, default: bool) -> bool {
+    match answer.trim().to_lowercase().as_str() {
+        "" => default,
+        answer => matches!(answer, "y" | "yes"),
+    }
+}
+
+// Set once from the editor when --platform-from-program is used, the host is used otherwise
+static PROGRAM_ARCH: OnceLock<&'static str> = OnceLock::new();
+
+pub fn set_program_arch(arch: &'static str) {
+    let _ = PROGRAM_ARCH.set(arch);
+}
+
+// Replaces the platform of the machine, set once from --target-platform to download for another one
+static TARGET_PLATFORM: OnceLock<TargetPlatform> = OnceLock::new();
+
+pub fn set_target_platform(platform: TargetPlatform) {
+    let _ = TARGET_PLATFORM.set(platform);
+}
+
 pub fn get_target_platform() -> TargetPlatform {
-    let arch = match env::consts::ARCH {
-        "x86" => "ia32",
-        "x86_64" => "x64",
-        "arm" => "armhf",
-        "aarch64" => "arm64",
-        _ => "x64",
-    };
+    if let Some(platform) = TARGET_PLATFORM.get() {
+        return *platform;
+    }
+
+    let arch = PROGRAM_ARCH.get().copied().unwrap_or_else(host_arch);
 
     let os = match env::consts::OS {
         "windows" => "win32",
@@ -72,7 +913,105 @@ pub fn get_target_platform() -> TargetPlatform {
         _ => "linux",
     };
 
-    TargetPlatform::from_str(&format!("{}-{}", os, arch)).unwrap()
+    // Not every arch is built for every os, darwin-ia32 for one, those get the universal build
+    format!("{}-{}", os, arch)
+        .parse()
+        .unwrap_or(TargetPlatform::Universal)
+}
+
+fn host_arch() -> &'static str {
+    match env::consts::ARCH {
+        "x86" => "ia32",
+        // An Intel build running through Rosetta is on an Apple Silicon Mac, where the editor is arm64
+        "x86_64" if cfg!(target_os = "macos") && rosetta_translated() => "arm64",
+        "x86_64" => "x64",
+        "arm" => "armhf",
+        "aarch64" => "arm64",
+        _ => "x64",
+    }
+}
+
+// sysctl.proc_translated only exists on Apple Silicon and is 1 for a process translated by Rosetta
+fn rosetta_translated() -> bool {
+    Command::new("sysctl")
+        .args(["-n", "sysctl.proc_translated"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "1")
+}
+
+// `code --version` prints the version, the commit and then the architecture the editor was built for
+fn version_line(program: &str, line: usize) -> Option<String> {
+    let output = Command::new(program).arg("--version").output().ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(line)
+        .map(|line| line.trim().to_string())
+}
+
+// Differs from the host when the editor runs under emulation
+pub fn program_arch(program: &str) -> Option<&'static str> {
+    match version_line(program, 2)?.as_str() {
+        "ia32" => Some("ia32"),
+        "x64" => Some("x64"),
+        "arm" => Some("armhf"),
+        "arm64" => Some("arm64"),
+        _ => None,
+    }
+}
+
+pub fn program_version(program: &str) -> Option<String> {
+    version_line(program, 0).filter(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+// Reads the engine the way the editor does: ^1.78.0 needs 1.78.0 or newer with the same major,
+// >=1.78.0 needs 1.78.0 or newer and a plain version needs that one. * and x parts match anything
+pub fn engine_compatible(engine: &str, editor: &str) -> bool {
+    let engine = engine.trim();
+    let (same_major, at_least, required) =
+        match (engine.strip_prefix('^'), engine.strip_prefix(">=")) {
+            (Some(required), _) => (true, true, required),
+            (_, Some(required)) => (false, true, required),
+            _ => (true, false, engine),
+        };
+
+    let parts = |version: &str| -> Vec<Option<u64>> {
+        version
+            .trim()
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect()
+    };
+
+    let (required, editor) = (parts(required), parts(editor));
+    let editor_part = |i: usize| editor.get(i).copied().flatten().unwrap_or(0);
+
+    if same_major
+        && required
+            .first()
+            .copied()
+            .flatten()
+            .is_some_and(|major| major != editor_part(0))
+    {
+        return false;
+    }
+
+    for (i, part) in required.iter().enumerate() {
+        match part {
+            None => return true,
+            Some(part) if *part == editor_part(i) => continue,
+            Some(part) => return at_least && editor_part(i) > *part,
+        }
+    }
+
+    true
 }
 
 #[derive(Error, Debug)]
@@ -101,23 +1040,447 @@ pub enum Error {
     #[error("Couldn't find the program used to install the extension.")]
     Command(#[source] std::io::Error),
 
+    #[error("Couldn't find {}, {}.", .0, .1)]
+    ProgramNotFound(String, String),
+
+    #[cfg(feature = "clipboard")]
+    #[error("Couldn't copy to the clipboard: {}", .0)]
+    Clipboard(String),
+
+    #[cfg(not(feature = "clipboard"))]
+    #[error("This build doesn't support the clipboard, rebuild it with --features clipboard")]
+    ClipboardUnsupported(),
+
+    #[error("{} doesn't support profiles, use --no-profile-check to pass it anyway.", .0)]
+    ProfileUnsupported(String),
+
+    #[error("{} couldn't install the extension: {}", .0, .1)]
+    Install(String, String),
+
+    #[error("{} couldn't list the installed extensions: {}", .0, .1)]
+    ListInstalled(String, String),
+
+    #[error("Couldn't find {} on {}.", .0, .1)]
+    RemoteProgram(String, String),
+
+    #[error("Something went wrong while {}.", .0)]
+    Remote(String),
+
+    #[error("Couldn't run {}, it's needed to install on another machine.", .0)]
+    RemoteCommand(String, #[source] std::io::Error),
+
     #[error("The index you selected is invalid.")]
     IndexOutOfBound(),
 
-    #[error("Couldn't parse a string to an integer.")]
-    ParseInt(ParseIntError),
+    #[error("\"{}\" isn't a valid index, pick one between 1 and {}.", .0, .1)]
+    InvalidIndex(String, usize),
+
+    #[error("The input was closed before an extension was picked.")]
+    InputClosed(),
+
+    #[error("No extension was picked.")]
+    Cancelled(),
+
+    #[error("The selector failed: {}", .0)]
+    Selector(#[source] io::Error),
 
     #[error("Couldn't parse a url.")]
     UrlParse(),
 
     #[error("Error while trying to flush the buffer: {:?}", .0)]
     Flush(#[source] std::io::Error),
+
+    #[error("The deadline was reached before the operation could finish.")]
+    Deadline(),
+
+    #[error("Couldn't build the http client: {}", .0)]
+    ClientBuild(#[source] reqwest::Error),
+
+    #[error("The token can't be sent in a header.")]
+    InvalidToken(),
+
+    #[error("The probe failed: {}", .0)]
+    Probe(String),
+
+    #[error("{} extension(s) of the batch couldn't be downloaded.", .0)]
+    Batch(usize),
+
+    #[error("{} isn't a publisher.name id", .0)]
+    NotAnId(String),
+
+    #[error("{} sidecar(s) couldn't be refreshed.", .0)]
+    RefreshMetadata(usize),
+
+    #[error("{} isn't a metadata sidecar, it has no id", .0)]
+    UnknownSidecar(String),
+
+    #[error("{} file(s) failed the verification.", .0)]
+    VerifyFailed(usize),
+
+    #[error("{} partial download(s) couldn't be finished.", .0)]
+    ResumeAll(usize),
+
+    #[error("No url was recorded for {}, download the extension again to resume it", .0)]
+    UnknownPartial(String),
+
+    #[error("The download is incomplete, expected {} bytes but got {}.", .expected, .got)]
+    IncompleteDownload { expected: u64, got: u64 },
+
+    #[error("Not enough disk space, the download needs {} but only {} are available", format_size(*.needed as usize), format_size(*.available as usize))]
+    InsufficientSpace { needed: u64, available: u64 },
+
+    #[error("The marketplace didn't give any file or asset uri to download.")]
+    NoAssets(),
+
+    #[error("{} has nothing to download, it may be restricted in your region or have been removed", .0)]
+    NotAvailable(String),
+
+    #[error("None of the results for {} is {}", .1, .0)]
+    NotInResults(String, String),
+
+    #[error("The download of {} is corrupt: {}", .0, .1)]
+    CorruptDownload(String, String),
+
+    #[error("Asked for {} but the marketplace answered with {}", .requested, .got)]
+    IdMismatch { requested: String, got: String },
+
+    #[error("No version of {} works with the editor {}, the latest one needs {}", .id, .engine, .required)]
+    Incompatible {
+        id: String,
+        engine: String,
+        required: String,
+    },
+
+    #[error("{} has no version {}, the closest ones are: {}", .id, .version, .nearby)]
+    VersionNotFound {
+        id: String,
+        version: String,
+        nearby: String,
+    },
+
+    #[error("The server failed: {}", .0)]
+    Serve(#[source] io::Error),
+
+    #[error("The download stalled, no data arrived for {} seconds", .0)]
+    Stalled(u64),
+
+    #[error("The marketplace doesn't support the api version {}: {}", .0, .1)]
+    ApiVersion(String, String),
+
+    #[error("The marketplace refused the search ({}): {}", .0, .1)]
+    Query(String, String),
+
+    #[error("The marketplace answered without any results.")]
+    EmptyResponse(),
+
+    #[error("Couldn't read the vsix archive: {}", .0)]
+    Zip(#[source] zip::result::ZipError),
+
+    #[error("The vsix doesn't contain {}", .0)]
+    MissingEntry(String),
+
+    #[error("{} isn't valid json: {}", .0, .1)]
+    InvalidJson(String, #[source] serde_json::Error),
+
+    #[error("The lockfile isn't valid json: {}", .0)]
+    Lockfile(#[source] serde_json::Error),
+
+    #[error("The history can't be saved: {}", .0)]
+    History(#[source] serde_json::Error),
+
+    #[error("{} isn't a valid config: {}", .0, .1)]
+    InvalidConfig(String, #[source] toml::de::Error),
+
+    #[error("{} isn't a target platform", .0)]
+    UnknownPlatform(String),
+
+    #[error("The vsix doesn't match what was asked for, expected {} but its manifest is {}", .expected, .got)]
+    ManifestMismatch { expected: String, got: String },
+
+    #[error("Couldn't send the output to stderr: {}", .0)]
+    Redirect(String),
+
+    #[error("The response has fields that aren't expected: {}", .0)]
+    UnknownFields(String),
+
+    #[error("{} doesn't contribute {}", .id, .missing)]
+    MissingContribution { id: String, missing: String },
+
+    #[error("{} isn't listed in {}", .0, .1)]
+    MissingChecksum(String, String),
+
+    #[error("The hash of {} doesn't match, expected {} but got {}", .key, .expected, .got)]
+    ChecksumMismatch {
+        key: String,
+        expected: String,
+        got: String,
+    },
+}
+
+// Callers boxing the error (anyhow, eyre, threads) rely on these bounds, this fails to compile if they break
+const _: () = {
+    const fn assert_error<T: std::error::Error + Send + Sync + 'static>() {}
+    assert_error::<Error>();
+};
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum ProgressEvent<'a> {
+    Progress { bytes: usize, total: u64 },
+    Done { path: &'a str },
+    Error { message: String },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BarStyle {
+    Ascii,
+    Unicode,
+    Dots,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    // Auto follows the NO_COLOR convention and leaves pipes and files uncolored
+    pub fn enabled(self, stream: impl IsTerminal) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && stream.is_terminal()
+            }
+        }
+    }
+}
+
+// What the server said about the file when a resumable download started
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct PartialMeta {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub total: Option<u64>,
+}
+
+// Written next to a .part file, the urls let --resume-all finish it without the extension
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PartialSidecar {
+    #[serde(flatten)]
+    pub meta: PartialMeta,
+    #[serde(default)]
+    pub urls: Vec<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchStatus {
+    Downloaded,
+    Installed,
+    Duplicate,
+    Filtered,
+    Failed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchResult {
+    pub requested: String,
+    pub id: Option<String>,
+    pub version: Option<String>,
+    pub status: BatchStatus,
+    pub path: Option<String>,
+    pub bytes: Option<u64>,
+    pub duration: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Metadata<'a> {
+    pub id: String,
+    pub publisher: &'a str,
+    pub verified: bool,
+    pub name: &'a str,
+    pub display_name: &'a str,
+    pub description: Option<&'a str>,
+    pub version: &'a str,
+    pub platform: Option<String>,
+    pub pre_release: bool,
+    pub flags: &'a str,
+    pub last_updated: &'a str,
+    pub published_date: &'a str,
+    pub release_date: &'a str,
+    pub installs: u64,
+}
+
+impl Metadata<'_> {
+    pub fn new(extension: &Extension, index: usize) -> Metadata<'_> {
+        let version = &extension.versions[index];
+
+        Metadata {
+            id: extension.id(),
+            publisher: &extension.publisher.publisherName,
+            verified: extension.publisher.is_verified(),
+            name: &extension.extensionName,
+            display_name: &extension.displayName,
+            description: extension.shortDescription.as_deref(),
+            version: &version.version,
+            platform: version
+                .targetPlatform
+                .as_ref()
+                .map(|platform| platform.to_string()),
+            pre_release: version.is_pre_release(),
+            flags: &extension.flags,
+            last_updated: &extension.lastUpdated,
+            published_date: &extension.publishedDate,
+            release_date: &extension.releaseDate,
+            installs: extension.installs(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Resolution<'a> {
+    pub publisher: &'a str,
+    pub name: &'a str,
+    pub version: &'a str,
+    pub platform: Option<String>,
+    pub url: String,
+    pub size: Option<u64>,
+}
+
+// The details of the download with the path it was saved or installed from
+#[derive(Serialize, Debug)]
+pub struct DownloadOutput<'a> {
+    #[serde(flatten)]
+    pub metadata: Metadata<'a>,
+    pub path: &'a str,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Jitter {
+    None,
+    Full,
+    Decorrelated,
+}
+
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(10);
+
+// Exponential backoff between retries, the jitter keeps machines that failed at the same time
+// from all retrying at the same time too
+pub struct Backoff {
+    jitter: Jitter,
+    attempt: u32,
+    previous: Duration,
+}
+
+impl Backoff {
+    pub fn new(jitter: Jitter) -> Backoff {
+        Backoff {
+            jitter,
+            attempt: 0,
+            previous: RETRY_BASE_DELAY,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let exponential = RETRY_BASE_DELAY
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(RETRY_MAX_DELAY);
+        self.attempt += 1;
+
+        let delay = match self.jitter {
+            Jitter::None => exponential,
+            Jitter::Full => exponential.mul_f64(fastrand::f64()),
+            Jitter::Decorrelated => {
+                let upper = self.previous.saturating_mul(3);
+                (RETRY_BASE_DELAY + (upper - RETRY_BASE_DELAY).mul_f64(fastrand::f64()))
+                    .min(RETRY_MAX_DELAY)
+            }
+        };
+
+        self.previous = delay;
+        delay
+    }
+}
+
+// The real stdout once --suppress-success-output or --json pointed it at stderr, only saved paths
+// and json go there
+static PATH_OUTPUT: Mutex<Option<FileDescriptor>> = Mutex::new(None);
+
+pub fn redirect_stdout() -> Result<(), Error> {
+    io::stdout().flush().map_err(Error::Flush)?;
+
+    let stdout = FileDescriptor::redirect_stdio(&io::stderr(), StdioDescriptor::Stdout)
+        .map_err(|error| Error::Redirect(error.to_string()))?;
+
+    if let Ok(mut output) = PATH_OUTPUT.lock() {
+        *output = Some(stdout);
+    }
+
+    Ok(())
+}
+
+pub fn print_saved_path(path: &str) {
+    if let Ok(mut output) = PATH_OUTPUT.lock() {
+        if let Some(output) = output.as_mut() {
+            let _ = writeln!(output, "{}", path);
+        }
+    }
+}
+
+// Goes to the real stdout even when it was redirected
+pub fn print_output(text: &str) {
+    if let Ok(mut output) = PATH_OUTPUT.lock() {
+        if let Some(output) = output.as_mut() {
+            let _ = writeln!(output, "{}", text);
+            return;
+        }
+    }
+
+    println!("{}", text);
+}
+
+// Whether print_error makes the errors red, set once from the arguments
+static ERROR_COLOR: AtomicBool = AtomicBool::new(false);
+
+pub fn set_error_color(enabled: bool) {
+    ERROR_COLOR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn print_error(error: &Error) {
+    if ERROR_COLOR.load(Ordering::Relaxed) {
+        eprintln!("{}{}{}", Ansi::Red, error, Ansi::Reset);
+    } else {
+        eprintln!("{}", error);
+    }
+}
+
+// Whether Glyph prints symbols or their ascii equivalent, set once from the arguments
+static EMOJI: AtomicBool = AtomicBool::new(false);
+
+pub fn set_emoji(enabled: bool) {
+    EMOJI.store(enabled, Ordering::Relaxed);
+}
+
+pub enum Glyph {
+    Ok,
+    Failed,
+    Download,
 }
 
 pub enum Ansi {
     ClearLine,
     CursorUp,
     CursorDown,
+    Background(u8, u8, u8),
+    Red,
+    Yellow,
+    Green,
+    Reset,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -183,12 +1546,22 @@ pub struct Extension {
     pub releaseDate: String,
     pub shortDescription: Option<String>,
     pub versions: Vec<Versions>,
+    #[serde(default)]
+    pub statistics: Vec<Statistic>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+pub struct Statistic {
+    pub statisticName: String,
+    pub value: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[allow(non_snake_case)]
 pub struct RequestOptions {
     pub filters: Vec<RequestFilters>,
+    pub flags: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -236,7 +1609,7 @@ pub enum FilterType {
 }
 
 // https://github.com/microsoft/vscode/blob/main/src/vs/platform/extensionManagement/common/extensionGalleryService.ts#L103
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ValueEnum, Clone, Copy)]
 pub enum RequestFlags {
     None = 0x0,
     IncludeVersions = 0x1,
@@ -253,45 +1626,70 @@ pub enum RequestFlags {
     IncludeNameConflictInfo = 0x8000,
 }
 
+// The files and asset uris are only in the answer when asked for, the version properties hold the pre-release and pack info
+pub fn query_flags() -> i32 {
+    RequestFlags::IncludeVersions as i32
+        | RequestFlags::IncludeFiles as i32
+        | RequestFlags::IncludeVersionProperties as i32
+        | RequestFlags::IncludeAssetUri as i32
+        | RequestFlags::IncludeStatistics as i32
+}
+
 // https://github.com/microsoft/vscode/blob/main/src/vs/platform/extensions/common/extensions.ts#L306
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Serialize, Deserialize, ValueEnum, Debug, PartialEq, Eq, Clone, Copy)]
 pub enum TargetPlatform {
     #[serde(rename = "win32-ia32")]
+    #[value(name = "win32-ia32")]
     Win32ia32,
     #[serde(rename = "win32-x64")]
+    #[value(name = "win32-x64")]
     Win32X64,
     #[serde(rename = "win32-arm64")]
+    #[value(name = "win32-arm64")]
     Win32Arm64,
 
     #[serde(rename = "linux-ia32")]
+    #[value(name = "linux-ia32")]
     Linuxia32,
     #[serde(rename = "linux-x64")]
+    #[value(name = "linux-x64")]
     LinuxX64,
     #[serde(rename = "linux-arm64")]
+    #[value(name = "linux-arm64")]
     LinuxArm64,
     #[serde(rename = "linux-armhf")]
+    #[value(name = "linux-armhf")]
     LinuxArmhf,
 
     #[serde(rename = "alpine-ia32")]
+    #[value(name = "alpine-ia32")]
     Alpineia32,
     #[serde(rename = "alpine-x64")]
+    #[value(name = "alpine-x64")]
     AlpineX64,
     #[serde(rename = "alpine-arm64")]
+    #[value(name = "alpine-arm64")]
     AlpineArm64,
 
     #[serde(rename = "darwin-x64")]
+    #[value(name = "darwin-x64")]
     DarwinX64,
     #[serde(rename = "darwin-arm64")]
+    #[value(name = "darwin-arm64")]
     DarwinArm64,
 
     #[serde(rename = "web")]
+    #[value(name = "web")]
     Web,
 
     #[serde(rename = "universal")]
+    #[value(name = "universal")]
     Universal,
     #[serde(rename = "unknown")]
+    #[value(skip)]
     Unknown,
     #[serde(rename = "undefined")]
+    #[value(skip)]
     Undefined,
 }
 
@@ -299,26 +1697,277 @@ impl FromStr for TargetPlatform {
     type Err = ();
     fn from_str(input: &str) -> Result<TargetPlatform, Self::Err> {
         match input {
+            "win32-ia32" => Ok(TargetPlatform::Win32ia32),
             "win32-x64" => Ok(TargetPlatform::Win32X64),
             "win32-arm64" => Ok(TargetPlatform::Win32Arm64),
 
+            "linux-ia32" => Ok(TargetPlatform::Linuxia32),
             "linux-x64" => Ok(TargetPlatform::LinuxX64),
             "linux-armhf" => Ok(TargetPlatform::LinuxArmhf),
             "linux-arm64" => Ok(TargetPlatform::LinuxArm64),
 
+            "alpine-ia32" => Ok(TargetPlatform::Alpineia32),
+            "alpine-x64" => Ok(TargetPlatform::AlpineX64),
+            "alpine-arm64" => Ok(TargetPlatform::AlpineArm64),
+
             "darwin-x64" => Ok(TargetPlatform::DarwinX64),
             "darwin-arm64" => Ok(TargetPlatform::DarwinArm64),
+
+            "web" => Ok(TargetPlatform::Web),
+            "universal" => Ok(TargetPlatform::Universal),
             _ => Err(()),
         }
     }
 }
 
+impl Publisher {
+    pub fn is_verified(&self) -> bool {
+        self.isDomainVerified || self.flags.split(", ").any(|flag| flag == "verified")
+    }
+}
+
+impl Extension {
+    // Extensions restricted in a region or removed can be listed without any version or vsix
+    pub fn is_available(&self) -> bool {
+        self.versions.iter().any(|version| version.has_vsix())
+    }
+
+    pub fn id(&self) -> String {
+        format!("{}.{}", self.publisher.publisherName, self.extensionName)
+    }
+
+    // Missing when the query didn't ask for statistics, which counts as no install at all
+    pub fn installs(&self) -> u64 {
+        self.statistics
+            .iter()
+            .find(|statistic| statistic.statisticName == "install")
+            .map(|statistic| statistic.value as u64)
+            .unwrap_or(0)
+    }
+
+    // Identifies a single downloadable file as id@version@platform
+    pub fn artifact_key(&self, index: usize) -> String {
+        let version = &self.versions[index];
+
+        format!(
+            "{}@{}@{}",
+            self.id().to_lowercase(),
+            version.version,
+            version.targetPlatform.unwrap_or(TargetPlatform::Universal)
+        )
+    }
+}
+
+impl Versions {
+    pub fn has_vsix(&self) -> bool {
+        self.files
+            .iter()
+            .any(|file| file.assetType == VSIX_ASSET_TYPE)
+            || !self.assetUri.is_empty()
+            || !self.fallbackAssetUri.is_empty()
+    }
+
+    pub fn is_pre_release(&self) -> bool {
+        self.properties
+            .iter()
+            .any(|property| property.key == PRE_RELEASE_PROPERTY && property.value == "true")
+    }
+
+    // The editor versions this version installs in, like ^1.78.0
+    pub fn engine(&self) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|property| property.key == ENGINE_PROPERTY)
+            .map(|property| property.value.as_str())
+    }
+
+    // Ids of the extensions bundled by an extension pack, empty for any other extension
+    pub fn pack_members(&self) -> Vec<String> {
+        self.property_ids(EXTENSION_PACK_PROPERTY)
+    }
+
+    // Ids of the extensions the editor needs installed before this one works
+    pub fn dependencies(&self) -> Vec<String> {
+        self.property_ids(EXTENSION_DEPENDENCIES_PROPERTY)
+    }
+
+    fn property_ids(&self, key: &str) -> Vec<String> {
+        self.properties
+            .iter()
+            .find(|property| property.key == key)
+            .map(|property| {
+                property
+                    .value
+                    .split(',')
+                    .map(|id| id.trim().to_string())
+                    .filter(|id| !id.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Only the files that are listed, there is no asset uri to build the url from for this one
+    pub fn sha256_url(&self) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|file| file.assetType == SHA256_ASSET_TYPE)
+            .map(|file| file.source.as_str())
+    }
+
+    pub fn vsix_urls(&self) -> Vec<String> {
+        self.asset_urls(VSIX_ASSET_TYPE)
+    }
+
+    // Falls back to building the url from the asset uris when the files array doesn't list the asset.
+    // Several files of the same type are ordered so every run tries them the same way:
+    // the ones naming the platform of the version first, then by url
+    pub fn asset_urls(&self, asset_type: &str) -> Vec<String> {
+        let platform = self.targetPlatform.map(|platform| platform.to_string());
+
+        let mut sources: Vec<&str> = self
+            .files
+            .iter()
+            .filter(|file| file.assetType == asset_type)
+            .map(|file| file.source.as_str())
+            .collect();
+
+        if sources.is_empty() {
+            return [&self.assetUri, &self.fallbackAssetUri]
+                .into_iter()
+                .filter(|uri| !uri.is_empty())
+                .map(|uri| format!("{}/{}", uri, asset_type))
+                .collect();
+        }
+
+        sources.sort_by_key(|source| {
+            let other_platform = match &platform {
+                Some(platform) => !source.contains(platform.as_str()),
+                None => false,
+            };
+            (other_platform, *source)
+        });
+        sources.dedup();
+
+        sources
+            .into_iter()
+            .map(|source| source.to_string())
+            .collect()
+    }
+}
+
+impl PartialMeta {
+    pub fn from_headers(headers: &HeaderMap) -> PartialMeta {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+
+        PartialMeta {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            total: header(CONTENT_LENGTH).and_then(|value| value.parse().ok()),
+        }
+    }
+}
+
+impl BatchResult {
+    pub fn new(requested: &str) -> BatchResult {
+        BatchResult {
+            requested: requested.to_string(),
+            id: None,
+            version: None,
+            status: BatchStatus::Failed,
+            path: None,
+            bytes: None,
+            duration: 0.0,
+            error: None,
+        }
+    }
+}
+
+impl ProgressEvent<'_> {
+    pub fn emit(&self) {
+        if let Ok(line) = serde_json::to_string(self) {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+impl std::fmt::Display for TargetPlatform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TargetPlatform::Win32ia32 => "win32-ia32",
+            TargetPlatform::Win32X64 => "win32-x64",
+            TargetPlatform::Win32Arm64 => "win32-arm64",
+
+            TargetPlatform::Linuxia32 => "linux-ia32",
+            TargetPlatform::LinuxX64 => "linux-x64",
+            TargetPlatform::LinuxArm64 => "linux-arm64",
+            TargetPlatform::LinuxArmhf => "linux-armhf",
+
+            TargetPlatform::Alpineia32 => "alpine-ia32",
+            TargetPlatform::AlpineX64 => "alpine-x64",
+            TargetPlatform::AlpineArm64 => "alpine-arm64",
+
+            TargetPlatform::DarwinX64 => "darwin-x64",
+            TargetPlatform::DarwinArm64 => "darwin-arm64",
+
+            TargetPlatform::Web => "web",
+
+            TargetPlatform::Universal => "universal",
+            TargetPlatform::Unknown => "unknown",
+            TargetPlatform::Undefined => "undefined",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl std::fmt::Display for Glyph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let emoji = EMOJI.load(Ordering::Relaxed);
+
+        let glyph = match self {
+            Glyph::Ok if emoji => "✓",
+            Glyph::Ok => "[OK]",
+            Glyph::Failed if emoji => "✗",
+            Glyph::Failed => "[X]",
+            Glyph::Download if emoji => "⬇",
+            Glyph::Download => "->",
+        };
+
+        write!(f, "{}", glyph)
+    }
+}
+
 impl std::fmt::Display for Ansi {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Ansi::ClearLine => write!(f, "\x1B[K"),
             Ansi::CursorUp => write!(f, "\x1B[1A"),
             Ansi::CursorDown => write!(f, "\x1B[1B"),
+            Ansi::Background(r, g, b) => write!(f, "\x1B[48;2;{};{};{}m", r, g, b),
+            Ansi::Red => write!(f, "\x1B[31m"),
+            Ansi::Yellow => write!(f, "\x1B[33m"),
+            Ansi::Green => write!(f, "\x1B[32m"),
+            Ansi::Reset => write!(f, "\x1B[0m"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_answers_take_the_default() {
+        for answer in ["", "   ", "\n", "y", "YES"] {
+            assert!(is_yes(answer, true), "{:?}", answer);
+        }
+        for answer in ["n", "no", "x"] {
+            assert!(!is_yes(answer, true), "{:?}", answer);
         }
     }
 }
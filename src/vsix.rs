@@ -0,0 +1,296 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::{Builder, Header};
+
+use serde_json::Value;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use crate::utility::{strip_jsonc, Ansi, Error};
+
+// The files of the extension itself live under this folder inside the vsix
+pub const EXTENSION_DIR: &str = "extension";
+pub const MANIFEST_ENTRY: &str = "extension.vsixmanifest";
+
+pub fn read_entry(path: &str, name: &str) -> Result<String, Error> {
+    let file = File::open(path).map_err(Error::FileRead)?;
+    let mut archive = ZipArchive::new(file).map_err(Error::Zip)?;
+    let mut entry = archive.by_name(name).map_err(|error| match error {
+        ZipError::FileNotFound => Error::MissingEntry(name.to_string()),
+        error => Error::Zip(error),
+    })?;
+
+    let mut content = String::new();
+    entry
+        .read_to_string(&mut content)
+        .map_err(Error::FileRead)?;
+
+    Ok(content)
+}
+
+// Reading every entry to the end makes the zip crate check their crc
+pub fn verify_archive(path: &str) -> Result<(), Error> {
+    let file = File::open(path).map_err(Error::FileRead)?;
+    let mut archive = ZipArchive::new(file).map_err(Error::Zip)?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(Error::Zip)?;
+        io::copy(&mut entry, &mut io::sink()).map_err(Error::FileRead)?;
+    }
+
+    Ok(())
+}
+
+// A vsix is a zip with the vsixmanifest at its root, a truncated download fails one or the other
+pub fn verify_vsix(path: &str) -> Result<(), Error> {
+    verify_archive(path)?;
+    read_entry(path, MANIFEST_ENTRY).map(|_| ())
+}
+
+// Writes every entry of the vsix into a .tar.gz next to it and removes the vsix
+pub fn repackage_tar_gz(path: &str) -> Result<String, Error> {
+    let file = File::open(path).map_err(Error::FileRead)?;
+    let mut archive = ZipArchive::new(file).map_err(Error::Zip)?;
+
+    let tar_path = format!("{}.tar.gz", path.trim_end_matches(".vsix"));
+    let tar_file = File::create(&tar_path).map_err(Error::FileWrite)?;
+    let mut builder = Builder::new(GzEncoder::new(tar_file, Compression::default()));
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(Error::Zip)?;
+        if entry.is_dir() {
+            continue;
+        }
+
+        let name = entry.name().map_err(Error::Zip)?.to_string();
+
+        let mut content = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut content).map_err(Error::FileRead)?;
+
+        let mut header = Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(entry.unix_mode().unwrap_or(0o644));
+        header.set_cksum();
+
+        builder
+            .append_data(&mut header, name, content.as_slice())
+            .map_err(Error::FileWrite)?;
+    }
+
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(Error::FileWrite)?;
+
+    fs::remove_file(path).map_err(Error::FileDelete)?;
+
+    Ok(tar_path)
+}
+
+pub fn read_json_entry(path: &str, name: &str) -> Result<Value, Error> {
+    let content = read_entry(path, name)?;
+
+    serde_json::from_str(&strip_jsonc(&content))
+        .map_err(|error| Error::InvalidJson(name.to_string(), error))
+}
+
+pub fn read_package_json(path: &str) -> Result<Value, Error> {
+    read_json_entry(path, &format!("{}/package.json", EXTENSION_DIR))
+}
+
+// publisher.name@version as written by the extension itself
+pub fn manifest_id(path: &str) -> Result<String, Error> {
+    let package = read_package_json(path)?;
+    let field = |key: &str| package[key].as_str().unwrap_or_default().to_string();
+
+    Ok(format!(
+        "{}.{}@{}",
+        field("publisher"),
+        field("name"),
+        field("version")
+    ))
+}
+
+// The extensionDependencies then the extensionPack of the manifest
+pub fn manifest_dependencies(path: &str) -> Result<Vec<String>, Error> {
+    let package = read_package_json(path)?;
+    let ids = |key: &str| {
+        package[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|id| id.as_str())
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect::<Vec<String>>()
+    };
+
+    Ok([ids("extensionDependencies"), ids("extensionPack")].concat())
+}
+
+// The requested contribution points the manifest doesn't declare, an empty list counts as missing
+pub fn missing_contributions(path: &str, required: &[String]) -> Result<Vec<String>, Error> {
+    let package = read_package_json(path)?;
+    let contributes = &package["contributes"];
+
+    Ok(required
+        .iter()
+        .filter(|key| match &contributes[key.as_str()] {
+            Value::Array(entries) => entries.is_empty(),
+            Value::Object(entries) => entries.is_empty(),
+            Value::Null => true,
+            _ => false,
+        })
+        .cloned()
+        .collect())
+}
+
+pub fn print_manifest(path: &str, raw: bool) -> Result<(), Error> {
+    let package = read_package_json(path)?;
+
+    if raw {
+        let pretty = serde_json::to_string_pretty(&package)
+            .map_err(|error| Error::InvalidJson("package.json".to_string(), error))?;
+        println!("{}", pretty);
+        return Ok(());
+    }
+
+    let field = |key: &str| package[key].as_str().unwrap_or("-").to_string();
+
+    println!("Manifest:");
+    println!();
+    println!("\tName: {}", field("name"));
+    println!("\tDisplay name: {}", field("displayName"));
+    println!("\tVersion: {}", field("version"));
+    println!("\tPublisher: {}", field("publisher"));
+    println!(
+        "\tEngine: {}",
+        package["engines"]["vscode"].as_str().unwrap_or("-")
+    );
+
+    if let Some(events) = package["activationEvents"].as_array() {
+        println!("\tActivation events:");
+        for event in events {
+            println!("\t\t{}", event.as_str().unwrap_or_default());
+        }
+    }
+
+    if let Some(contributes) = package["contributes"].as_object() {
+        println!("\tContributes:");
+        for (key, value) in contributes {
+            match value {
+                Value::Array(entries) => println!("\t\t{} ({})", key, entries.len()),
+                Value::Object(entries) => println!("\t\t{} ({})", key, entries.len()),
+                _ => println!("\t\t{}", key),
+            }
+        }
+    }
+
+    println!();
+
+    Ok(())
+}
+
+// Colors shown first when the theme defines them, they give the best idea of what the editor looks like
+const PREVIEW_COLORS: [&str; 10] = [
+    "editor.background",
+    "editor.foreground",
+    "editor.selectionBackground",
+    "editorCursor.foreground",
+    "editorLineNumber.foreground",
+    "activityBar.background",
+    "sideBar.background",
+    "statusBar.background",
+    "tab.activeBackground",
+    "terminal.background",
+];
+
+pub fn preview_themes(path: &str) -> Result<(), Error> {
+    let package = read_package_json(path)?;
+
+    let themes = match package["contributes"]["themes"].as_array() {
+        Some(themes) if !themes.is_empty() => themes,
+        _ => {
+            println!("This extension doesn't contribute any color theme.");
+            return Ok(());
+        }
+    };
+
+    for theme in themes {
+        let label = theme["label"].as_str().unwrap_or("Unnamed theme");
+        let theme_path = match theme["path"].as_str() {
+            Some(theme_path) => theme_path.trim_start_matches("./"),
+            None => continue,
+        };
+
+        println!("{}:", label);
+
+        let colors = match read_json_entry(path, &format!("{}/{}", EXTENSION_DIR, theme_path)) {
+            Ok(content) => content["colors"].clone(),
+            Err(error) => {
+                println!("\t{}", error);
+                continue;
+            }
+        };
+
+        let colors = match colors.as_object() {
+            Some(colors) => colors,
+            None => {
+                println!("\tThis theme doesn't define any editor color.");
+                continue;
+            }
+        };
+
+        let mut keys: Vec<&str> = PREVIEW_COLORS
+            .iter()
+            .copied()
+            .filter(|key| colors.contains_key(*key))
+            .collect();
+
+        if keys.is_empty() {
+            keys = colors
+                .keys()
+                .take(PREVIEW_COLORS.len())
+                .map(|key| key.as_str())
+                .collect();
+        }
+
+        for key in keys {
+            let hex = colors[key].as_str().unwrap_or_default();
+
+            match parse_hex_color(hex) {
+                Some((r, g, b)) => println!(
+                    "\t{}      {} {} {}",
+                    Ansi::Background(r, g, b),
+                    Ansi::Reset,
+                    hex,
+                    key
+                ),
+                None => println!("\t       {} {}", hex, key),
+            }
+        }
+
+        println!();
+    }
+
+    Ok(())
+}
+
+// Accepts #rgb, #rgba, #rrggbb and #rrggbbaa, the alpha channel is ignored
+fn parse_hex_color(hex: &str) -> Option<(u8, u8, u8)> {
+    let hex = hex.strip_prefix('#')?;
+
+    let channel = |i: usize, len: usize| {
+        let value = u8::from_str_radix(hex.get(i * len..(i + 1) * len)?, 16).ok()?;
+        Some(if len == 1 { value * 17 } else { value })
+    };
+
+    match hex.len() {
+        3 | 4 => Some((channel(0, 1)?, channel(1, 1)?, channel(2, 1)?)),
+        6 | 8 => Some((channel(0, 2)?, channel(1, 2)?, channel(2, 2)?)),
+        _ => None,
+    }
+}